@@ -0,0 +1,574 @@
+//! Attribute macro that generates the boilerplate around a system object: the field enum,
+//! its `FieldParameter` impl, `Default`, and the object traits from
+//! `fancy_stuff_with_reflection::internal`.
+
+use fancy_stuff_with_reflection_filter::{Comparison, Expression};
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr, Type,
+    Visibility,
+};
+
+/// Turns a struct of plain values into a system object.
+///
+/// ```ignore
+/// #[system_object(modify = "update_user", create = "make_user", delete = "remove_user")]
+/// pub struct User {
+///     #[field(id, create_param = "-id")]
+///     id: u32,
+///     #[field(param = "-set_name", reset_flag = "-reset_name", create_param = "-name")]
+///     name: String,
+///     #[field(param = "-set_roleid", min_version = "4.12")]
+///     role_id: Ref<Role>,
+/// }
+/// ```
+///
+/// Every member becomes a `Field<T, UserFields>`, where `UserFields` is a generated enum
+/// with one variant per member (override the name with `fields = "..."`). The object
+/// attributes `modify`, `create` and `delete` each implement the matching object trait
+/// with the given verb. With the `serde` feature, the object also implements `Serialize`
+/// and `Deserialize` as the plain struct of values.
+///
+/// Member attributes:
+/// * `id`: the member holding the object id. Exactly one member must have it. Give it a
+///   `create_param` if create commands can set the id; plans need this to match the
+///   objects they create.
+/// * `param`: the switch used to set the member in modify commands.
+/// * `create_param`: the switch used in create commands; defaults to `param`.
+/// * `reset_flag`: the flag used instead of `param` when the new value is empty.
+/// * `name`: the field name reported by the target system; defaults to the member name.
+/// * `min_version`, `max_version`: the versions of the target system supporting the member,
+///   such as `"4.12"` or `"8.5.0.3"`.
+/// * `versions`: a `VersionFilter` expression such as `">=4.12, <5.0 || =3.188"`, for
+///   members that a single range can't describe. The expression is checked at compile
+///   time and built with `VersionFilter`'s constructors.
+#[proc_macro_attribute]
+pub fn system_object(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut object = ObjectAttributes::default();
+    let object_parser = syn::meta::parser(|meta| object.parse(meta));
+    parse_macro_input!(attr with object_parser);
+
+    let input = parse_macro_input!(item as DeriveInput);
+
+    match expand(object, input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.into_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct ObjectAttributes {
+    fields: Option<Ident>,
+    modify: Option<LitStr>,
+    create: Option<LitStr>,
+    delete: Option<LitStr>,
+}
+
+impl ObjectAttributes {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("fields") {
+            let name: LitStr = meta.value()?.parse()?;
+            self.fields = Some(name.parse()?);
+        } else if meta.path.is_ident("modify") {
+            self.modify = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("create") {
+            self.create = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("delete") {
+            self.delete = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("unsupported system_object attribute"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FieldAttributes {
+    id: bool,
+    name: Option<LitStr>,
+    param: Option<LitStr>,
+    create_param: Option<LitStr>,
+    reset_flag: Option<LitStr>,
+    min_version: Option<LitStr>,
+    max_version: Option<LitStr>,
+    versions: Option<TokenStream2>,
+}
+
+impl FieldAttributes {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("id") {
+            self.id = true;
+        } else if meta.path.is_ident("name") {
+            self.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("param") {
+            self.param = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("create_param") {
+            self.create_param = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("reset_flag") {
+            self.reset_flag = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("min_version") {
+            self.min_version = Some(parse_version(&meta.value()?.parse()?)?);
+        } else if meta.path.is_ident("max_version") {
+            self.max_version = Some(parse_version(&meta.value()?.parse()?)?);
+        } else if meta.path.is_ident("versions") {
+            self.versions = Some(parse_versions(&meta.value()?.parse()?)?);
+        } else {
+            return Err(meta.error("unsupported field attribute"));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the version here so that mistakes point at the attribute rather than at a
+/// const evaluation panic inside the generated code.
+fn parse_version(version: &LitStr) -> syn::Result<LitStr> {
+    let value = version.value();
+    let value = value.strip_prefix(['v', 'V']).unwrap_or(&value);
+    let components = value.split('.').collect::<Vec<_>>();
+    if components.len() > 4 || components.iter().any(|part| part.parse::<u32>().is_err()) {
+        return Err(syn::Error::new(
+            version.span(),
+            "expected a version of the form \"major.minor[.patch[.build]]\"",
+        ));
+    }
+    Ok(version.clone())
+}
+
+/// Parses a `versions` expression with the grammar `VersionFilter`'s `FromStr` impl uses
+/// and returns the expression that builds it, so that a malformed filter is a compile
+/// error rather than a panic whenever the object is created.
+fn parse_versions(expression: &LitStr) -> syn::Result<TokenStream2> {
+    let parsed = fancy_stuff_with_reflection_filter::parse(&expression.value())
+        .map_err(|error| syn::Error::new(expression.span(), error))?;
+    build_filter(parsed, expression)
+}
+
+fn build_filter(expression: Expression, literal: &LitStr) -> syn::Result<TokenStream2> {
+    let crate_path = quote!(::fancy_stuff_with_reflection::internal);
+    Ok(match expression {
+        Expression::Comparison(comparison, version) => {
+            let constructor = match comparison {
+                Comparison::AtLeast => quote!(min_version),
+                Comparison::AtMost => quote!(max_version),
+                Comparison::Above => quote!(above),
+                Comparison::Below => quote!(below),
+                Comparison::Exactly => quote!(exactly),
+                Comparison::Excluding => quote!(excluding),
+            };
+            let version = parse_version(&LitStr::new(&version, literal.span()))?;
+            quote! {
+                #crate_path::VersionFilter::#constructor(
+                    #crate_path::SoftwareVersion::parse(#version)
+                )
+            }
+        }
+        Expression::All(left, right) => {
+            let (left, right) = (
+                build_filter(*left, literal)?,
+                build_filter(*right, literal)?,
+            );
+            quote!(#left.and(#right))
+        }
+        Expression::Any(left, right) => {
+            let (left, right) = (
+                build_filter(*left, literal)?,
+                build_filter(*right, literal)?,
+            );
+            quote!(#left.or(#right))
+        }
+        Expression::Not(filter) => {
+            let filter = build_filter(*filter, literal)?;
+            quote!(::std::ops::Not::not(#filter))
+        }
+    })
+}
+
+struct ObjectField {
+    ident: Ident,
+    ty: Type,
+    variant: Ident,
+    attributes: FieldAttributes,
+}
+
+fn expand(object: ObjectAttributes, mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let crate_path = quote!(::fancy_stuff_with_reflection::internal);
+    let object_ident = input.ident.clone();
+    let visibility = input.vis.clone();
+    let fields_ident = object
+        .fields
+        .clone()
+        .unwrap_or_else(|| format_ident!("{}Fields", object_ident));
+
+    let Data::Struct(data) = &mut input.data else {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "system_object can only be used on structs",
+        ));
+    };
+    let Fields::Named(named) = &mut data.fields else {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "system_object requires a struct with named fields",
+        ));
+    };
+
+    let mut fields = Vec::new();
+    for field in named.named.iter_mut() {
+        let mut attributes = FieldAttributes::default();
+        let mut error = None;
+        field.attrs.retain(|attr| {
+            if !attr.path().is_ident("field") {
+                return true;
+            }
+            if let Err(err) = attr.parse_nested_meta(|meta| attributes.parse(meta)) {
+                error = Some(err);
+            }
+            false
+        });
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let ident = field.ident.clone().expect("named fields have identifiers");
+        if attributes.versions.is_some()
+            && (attributes.min_version.is_some() || attributes.max_version.is_some())
+        {
+            return Err(syn::Error::new(
+                ident.span(),
+                "versions can't be combined with min_version or max_version",
+            ));
+        }
+        if !attributes.id && attributes.param.is_none() {
+            return Err(syn::Error::new(
+                ident.span(),
+                "fields need either #[field(id)] or #[field(param = \"...\")]",
+            ));
+        }
+
+        let ty = field.ty.clone();
+        field.ty = syn::parse_quote!(#crate_path::Field<#ty, #fields_ident>);
+        fields.push(ObjectField {
+            variant: format_ident!("{}", to_upper_camel_case(&ident.to_string())),
+            ident,
+            ty,
+            attributes,
+        });
+    }
+
+    let id_field = match fields
+        .iter()
+        .filter(|field| field.attributes.id)
+        .collect::<Vec<_>>()[..]
+    {
+        [id_field] => &id_field.ident,
+        _ => {
+            return Err(syn::Error::new(
+                object_ident.span(),
+                "system objects need exactly one #[field(id)] member",
+            ))
+        }
+    };
+
+    let fields_enum = expand_fields_enum(&visibility, &fields_ident, &fields);
+    let field_parameter = expand_field_parameter(&crate_path, &fields_ident, &fields);
+    let default = expand_default(&crate_path, &object_ident, &fields_ident, &fields);
+
+    let field_idents = fields.iter().map(|field| &field.ident);
+    let value_types = fields.iter().map(|field| &field.ty);
+    let serde = quote! {
+        ::fancy_stuff_with_reflection::impl_object_serde! {
+            #object_ident { #(#field_idents: #value_types),* }
+        }
+    };
+
+    let command_names = [
+        (&object.create, quote!(Create)),
+        (&object.modify, quote!(Modify)),
+        (&object.delete, quote!(Delete)),
+    ]
+    .into_iter()
+    .filter_map(|(verb, command_type)| {
+        verb.as_ref()
+            .map(|verb| quote!(#crate_path::CommandType::#command_type => Some(#verb),))
+    });
+
+    let field_types = fields.iter().map(|field| &field.ty);
+    let system_object = quote! {
+        impl #crate_path::SystemObject for #object_ident {
+            type FieldEnum = #fields_ident;
+
+            fn command_name(command_type: #crate_path::CommandType) -> Option<&'static str> {
+                #[allow(unreachable_patterns)]
+                match command_type {
+                    #(#command_names)*
+                    _ => None,
+                }
+            }
+
+            fn register_fields(registry: &mut ::bevy_reflect::TypeRegistry) {
+                #(
+                    registry.register::<#crate_path::Field<#field_types, #fields_ident>>();
+                    ::fancy_stuff_with_reflection::register_field_serde!(
+                        registry,
+                        #crate_path::Field<#field_types, #fields_ident>
+                    );
+                )*
+            }
+        }
+
+        impl #crate_path::IdentifiableObject for #object_ident {
+            fn get_id(&self) -> u32 {
+                *self.#id_field
+            }
+        }
+    };
+
+    let verbs = [
+        (
+            object.modify,
+            quote!(ModifiableObject),
+            quote!(get_modify_command),
+        ),
+        (
+            object.create,
+            quote!(CreatableObject),
+            quote!(get_create_command),
+        ),
+        (
+            object.delete,
+            quote!(DeletableObject),
+            quote!(get_delete_command),
+        ),
+    ];
+    let verb_impls = verbs.into_iter().filter_map(|(verb, trait_name, method)| {
+        verb.map(|verb| {
+            quote! {
+                impl #crate_path::#trait_name for #object_ident {
+                    fn #method(&self) -> &'static str {
+                        #verb
+                    }
+                }
+            }
+        })
+    });
+
+    // The code generated by the `Reflect` derive calls trait methods unqualified.
+    Ok(quote! {
+        #[allow(unused_imports)]
+        use ::bevy_reflect::Reflect as _;
+
+        #[derive(::bevy_reflect::Reflect, Clone)]
+        #input
+
+        #fields_enum
+        #field_parameter
+        #default
+        #system_object
+        #(#verb_impls)*
+        #serde
+    })
+}
+
+fn expand_fields_enum(
+    visibility: &Visibility,
+    fields_ident: &Ident,
+    fields: &[ObjectField],
+) -> TokenStream2 {
+    let variants = fields.iter().enumerate().map(|(index, field)| {
+        let variant = &field.variant;
+        if index == 0 {
+            quote!(#[default] #variant)
+        } else {
+            quote!(#variant)
+        }
+    });
+
+    quote! {
+        #[derive(Default, Clone, ::bevy_reflect::Reflect)]
+        #visibility enum #fields_ident {
+            #(#variants,)*
+        }
+    }
+}
+
+fn expand_field_parameter(
+    crate_path: &TokenStream2,
+    fields_ident: &Ident,
+    fields: &[ObjectField],
+) -> TokenStream2 {
+    let arms = fields.iter().map(|field| {
+        let variant = &field.variant;
+        let attributes = &field.attributes;
+        let Some(param) = &attributes.param else {
+            let create_arm = attributes.create_param.as_ref().map(|create_param| {
+                quote! {
+                    (Self::#variant, #crate_path::CommandType::Create) => {
+                        #crate_path::Parameter::Parameter(#create_param, self.format_value(new_value))
+                    }
+                }
+            });
+            return quote! {
+                #create_arm
+                (Self::#variant, _) => unreachable!("identifier fields are only sent by create commands"),
+            };
+        };
+
+        let create_param = attributes.create_param.as_ref().unwrap_or(param);
+        let reset_arm = attributes.reset_flag.as_ref().map(|reset_flag| {
+            quote! {
+                (Self::#variant, _) if #crate_path::IsEmpty::is_empty(&**new_value) => {
+                    #crate_path::Parameter::Flag(#reset_flag)
+                }
+            }
+        });
+
+        quote! {
+            (Self::#variant, #crate_path::CommandType::Create) => {
+                #crate_path::Parameter::Parameter(#create_param, self.format_value(new_value))
+            }
+            #reset_arm
+            (Self::#variant, _) => {
+                #crate_path::Parameter::Parameter(#param, self.format_value(new_value))
+            }
+        }
+    });
+
+    let switch_arms = fields.iter().map(|field| {
+        let variant = &field.variant;
+        let attributes = &field.attributes;
+        let Some(param) = &attributes.param else {
+            let create_arm = attributes.create_param.as_ref().map(|create_param| {
+                quote! {
+                    (Self::#variant, #crate_path::CommandType::Create) => #crate_path::FieldSwitches {
+                        param: Some(#create_param),
+                        reset_flag: None,
+                    },
+                }
+            });
+            return quote! {
+                #create_arm
+                (Self::#variant, _) => ::std::default::Default::default(),
+            };
+        };
+
+        let create_param = attributes.create_param.as_ref().unwrap_or(param);
+        let reset_flag = match &attributes.reset_flag {
+            Some(reset_flag) => quote!(Some(#reset_flag)),
+            None => quote!(None),
+        };
+
+        quote! {
+            (Self::#variant, #crate_path::CommandType::Create) => #crate_path::FieldSwitches {
+                param: Some(#create_param),
+                reset_flag: None,
+            },
+            (Self::#variant, _) => #crate_path::FieldSwitches {
+                param: Some(#param),
+                reset_flag: #reset_flag,
+            },
+        }
+    });
+
+    let identifiers = fields
+        .iter()
+        .filter(|field| field.attributes.id)
+        .map(|field| &field.variant);
+
+    quote! {
+        impl #crate_path::FieldParameter for #fields_ident {
+            fn get_parameter<FieldType: #crate_path::FieldDataType>(
+                &self,
+                command_type: #crate_path::CommandType,
+                _old_value: &#crate_path::FieldInner<FieldType, Self>,
+                new_value: &#crate_path::FieldInner<FieldType, Self>,
+            ) -> #crate_path::Parameter {
+                match (self, command_type) {
+                    #(#arms)*
+                }
+            }
+
+            fn switches(
+                &self,
+                command_type: #crate_path::CommandType,
+            ) -> #crate_path::FieldSwitches {
+                match (self, command_type) {
+                    #(#switch_arms)*
+                }
+            }
+
+            fn is_identifier(&self) -> bool {
+                matches!(self, #(Self::#identifiers)|*)
+            }
+        }
+    }
+}
+
+fn expand_default(
+    crate_path: &TokenStream2,
+    object_ident: &Ident,
+    fields_ident: &Ident,
+    fields: &[ObjectField],
+) -> TokenStream2 {
+    let version = |version: &LitStr| quote!(#crate_path::SoftwareVersion::parse(#version));
+
+    let initialisers = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let variant = &field.variant;
+        let attributes = &field.attributes;
+        let name = attributes
+            .name
+            .clone()
+            .unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span()));
+
+        let filter = match (&attributes.min_version, &attributes.max_version) {
+            _ if attributes.versions.is_some() => attributes.versions.clone(),
+            (Some(min), Some(max)) => {
+                let (min, max) = (version(min), version(max));
+                Some(quote!(#crate_path::VersionFilter::version_range(#min, #max)))
+            }
+            (Some(min), None) => {
+                let min = version(min);
+                Some(quote!(#crate_path::VersionFilter::min_version(#min)))
+            }
+            (None, Some(max)) => {
+                let max = version(max);
+                Some(quote!(#crate_path::VersionFilter::max_version(#max)))
+            }
+            (None, None) => None,
+        };
+
+        match filter {
+            Some(filter) => quote! {
+                #ident: #crate_path::Field::new_versioned(#name, #fields_ident::#variant, #filter)
+            },
+            None => quote! {
+                #ident: #crate_path::Field::new(#name, #fields_ident::#variant)
+            },
+        }
+    });
+
+    quote! {
+        impl ::std::default::Default for #object_ident {
+            fn default() -> Self {
+                #object_ident {
+                    #(#initialisers,)*
+                }
+            }
+        }
+    }
+}
+
+fn to_upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,195 @@
+//! The grammar of version filter expressions such as `">=4.12, <5.0 || =3.188"`, shared
+//! by `VersionFilter`'s `FromStr` impl and the `versions` attribute of `system_object`,
+//! which checks filters at compile time. Versions are left as written; each user parses
+//! them into its own representation.
+
+use std::{error::Error, fmt};
+
+/// A parsed filter expression. `,` binds tighter than `||`, and both are left
+/// associative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    Comparison(Comparison, String),
+    All(Box<Expression>, Box<Expression>),
+    Any(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// `>=`
+    AtLeast,
+    /// `<=`
+    AtMost,
+    /// `>`
+    Above,
+    /// `<`
+    Below,
+    /// `=` or `==`
+    Exactly,
+    /// `!=`
+    Excluding,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedToken(String),
+    UnexpectedEnd,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(token) => {
+                write!(f, "unexpected `{}` in version filter", token)
+            }
+            ParseError::UnexpectedEnd => write!(f, "version filter ended unexpectedly"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+pub fn parse(expression: &str) -> Result<Expression, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+    };
+    let expression = parser.parse_any()?;
+    match parser.next() {
+        None => Ok(expression),
+        Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Comparison(&'static str),
+    Version(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Comparison(operator) => write!(f, "{}", operator),
+            Token::Version(version) => write!(f, "{}", version),
+            Token::Not => write!(f, "!"),
+            Token::And => write!(f, ","),
+            Token::Or => write!(f, "||"),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ParseError> {
+    const COMPARISONS: [&str; 7] = [">=", "<=", "==", "!=", ">", "<", "="];
+
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, length) = if let Some(operator) = COMPARISONS
+            .iter()
+            .find(|operator| rest.starts_with(**operator))
+        {
+            (Token::Comparison(operator), operator.len())
+        } else if rest.starts_with("||") {
+            (Token::Or, 2)
+        } else if c == '!' {
+            (Token::Not, 1)
+        } else if c == ',' {
+            (Token::And, 1)
+        } else if c == '(' {
+            (Token::Open, 1)
+        } else if c == ')' {
+            (Token::Close, 1)
+        } else if c.is_ascii_alphanumeric() {
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .unwrap_or(rest.len());
+            (Token::Version(String::from(&rest[..length])), length)
+        } else {
+            return Err(ParseError::UnexpectedToken(c.to_string()));
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `any := all ("||" all)*`, `all := unary ("," unary)*` and
+/// `unary := "!" unary | "(" any ")" | comparison version`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_any(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.parse_all()?;
+        while self.eat(&Token::Or) {
+            expression = Expression::Any(Box::new(expression), Box::new(self.parse_all()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_all(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            expression = Expression::All(Box::new(expression), Box::new(self.parse_unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expression::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let expression = self.parse_any()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+                    None => Err(ParseError::UnexpectedEnd),
+                }
+            }
+            Some(Token::Comparison(operator)) => {
+                let version = match self.next() {
+                    Some(Token::Version(version)) => version,
+                    Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                    None => return Err(ParseError::UnexpectedEnd),
+                };
+                let comparison = match operator {
+                    ">=" => Comparison::AtLeast,
+                    "<=" => Comparison::AtMost,
+                    ">" => Comparison::Above,
+                    "<" => Comparison::Below,
+                    "!=" => Comparison::Excluding,
+                    _ => Comparison::Exactly,
+                };
+                Ok(Expression::Comparison(comparison, version))
+            }
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
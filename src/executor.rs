@@ -0,0 +1,364 @@
+//! Running [`ModificationCommand`]s against a target system.
+
+use std::{collections::VecDeque, error::Error, fmt, io, process::Command};
+
+use crate::internal::{CommandError, ModificationCommand};
+
+/// What came back from running a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// The exit status, or `None` if the command was killed by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// The target system's error code, such as `CMMVC5753E`, if the output contains one.
+    pub error_code: Option<String>,
+}
+
+impl CommandOutput {
+    /// Builds an output, parsing the error code from `stderr`, or from `stdout` if
+    /// `stderr` has none.
+    pub fn new(status: Option<i32>, stdout: impl Into<String>, stderr: impl Into<String>) -> Self {
+        let stdout = stdout.into();
+        let stderr = stderr.into();
+        let error_code = parse_error_code(&stderr)
+            .or_else(|| parse_error_code(&stdout))
+            .map(String::from);
+        CommandOutput {
+            status,
+            stdout,
+            stderr,
+            error_code,
+        }
+    }
+
+    pub fn success(stdout: impl Into<String>) -> Self {
+        CommandOutput::new(Some(0), stdout, "")
+    }
+
+    pub fn failure(status: i32, stderr: impl Into<String>) -> Self {
+        CommandOutput::new(Some(status), "", stderr)
+    }
+
+    /// Whether the command exited with status 0 without reporting an error code.
+    pub fn is_success(&self) -> bool {
+        self.status == Some(0) && self.error_code.is_none()
+    }
+
+    /// The id a create command reports for the new object, as in
+    /// `User, id [46], successfully created`. Only a whole `id` word counts, not the end
+    /// of one such as `role_id [5]`.
+    pub fn created_id(&self) -> Option<u32> {
+        self.stdout
+            .match_indices("id [")
+            .find_map(|(index, label)| {
+                let starts_word = self.stdout[..index]
+                    .chars()
+                    .next_back()
+                    .is_none_or(|c| c.is_whitespace() || c == ',');
+                if !starts_word {
+                    return None;
+                }
+                let (id, _) = self.stdout[index + label.len()..].split_once(']')?;
+                id.parse().ok()
+            })
+    }
+}
+
+/// Finds the first word shaped like a target system error code: at least three
+/// uppercase letters, at least three digits, and a trailing severity letter, e.g.
+/// `CMMVC5753E`.
+pub fn parse_error_code(output: &str) -> Option<&str> {
+    output
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find(|word| is_error_code(word))
+}
+
+fn is_error_code(word: &str) -> bool {
+    let Some((severity, rest)) = word
+        .char_indices()
+        .last()
+        .map(|(index, c)| (c, &word[..index]))
+    else {
+        return false;
+    };
+    let prefix_len = rest
+        .find(|c: char| !c.is_ascii_uppercase())
+        .unwrap_or(rest.len());
+    let (prefix, digits) = rest.split_at(prefix_len);
+
+    severity.is_ascii_uppercase()
+        && prefix.len() >= 3
+        && digits.len() >= 3
+        && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Something that can run commands on a target system.
+pub trait CommandExecutor {
+    fn execute(&mut self, command: &ModificationCommand) -> io::Result<CommandOutput>;
+}
+
+/// Runs `command`, treating output that isn't a success as an error.
+pub(crate) fn run_checked(
+    executor: &mut impl CommandExecutor,
+    command: &ModificationCommand,
+) -> Result<CommandOutput, ApplyError> {
+    let output = executor.execute(command)?;
+    if output.is_success() {
+        Ok(output)
+    } else {
+        Err(ApplyError::Failed(output))
+    }
+}
+
+/// Runs commands as local processes, see [`ModificationCommand::to_argv`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalProcessExecutor;
+
+impl CommandExecutor for LocalProcessExecutor {
+    fn execute(&mut self, command: &ModificationCommand) -> io::Result<CommandOutput> {
+        let output = Command::from(command).output()?;
+        Ok(CommandOutput::new(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ))
+    }
+}
+
+/// Records every command instead of running it, answering with queued outputs and then
+/// with success.
+#[derive(Debug, Default, Clone)]
+pub struct RecordingExecutor {
+    commands: Vec<ModificationCommand>,
+    responses: VecDeque<CommandOutput>,
+}
+
+impl RecordingExecutor {
+    pub fn new() -> Self {
+        RecordingExecutor::default()
+    }
+
+    /// Queues the output returned for the next command that has no output queued yet.
+    pub fn respond_with(mut self, output: CommandOutput) -> Self {
+        self.responses.push_back(output);
+        self
+    }
+
+    pub fn commands(&self) -> &[ModificationCommand] {
+        &self.commands
+    }
+}
+
+impl CommandExecutor for RecordingExecutor {
+    fn execute(&mut self, command: &ModificationCommand) -> io::Result<CommandOutput> {
+        self.commands.push(command.clone());
+        Ok(self
+            .responses
+            .pop_front()
+            .unwrap_or_else(|| CommandOutput::success("")))
+    }
+}
+
+/// Why an object's changes could not be applied.
+#[derive(Debug)]
+pub enum ApplyError {
+    /// The command could not be generated; nothing was run.
+    Command(CommandError),
+    /// The command could not be run.
+    Io(io::Error),
+    /// The command ran and failed.
+    Failed(CommandOutput),
+    /// A create succeeded, but the target reports another id than the one requested.
+    UnexpectedId { requested: String, reported: u32 },
+    /// A create that left the id to the target succeeded, but its output names no id.
+    MissingId(CommandOutput),
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::Command(error) => write!(f, "could not generate command: {}", error),
+            ApplyError::Io(error) => write!(f, "could not run command: {}", error),
+            ApplyError::Failed(output) => match (&output.error_code, output.status) {
+                (Some(code), _) => write!(f, "command failed with {}", code),
+                (None, Some(status)) => write!(f, "command failed with status {}", status),
+                (None, None) => write!(f, "command was terminated"),
+            },
+            ApplyError::UnexpectedId {
+                requested,
+                reported,
+            } => write!(
+                f,
+                "object was created with id {} instead of {}",
+                reported, requested
+            ),
+            ApplyError::MissingId(_) => write!(f, "created object's id was not reported"),
+        }
+    }
+}
+
+impl Error for ApplyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApplyError::Command(error) => Some(error),
+            ApplyError::Io(error) => Some(error),
+            ApplyError::Failed(_) | ApplyError::UnexpectedId { .. } | ApplyError::MissingId(_) => {
+                None
+            }
+        }
+    }
+}
+
+impl From<CommandError> for ApplyError {
+    fn from(error: CommandError) -> Self {
+        ApplyError::Command(error)
+    }
+}
+
+impl From<io::Error> for ApplyError {
+    fn from(error: io::Error) -> Self {
+        ApplyError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{
+        CommandType, ModifiableObject, Parameter, SoftwareVersion, SystemObject, TargetSystem,
+    };
+    use crate::reference::Ref;
+    use crate::test_support::{stored_user, target};
+
+    #[test]
+    fn error_codes_are_parsed_from_output() {
+        assert_eq!(
+            parse_error_code("CMMVC5753E The specified object does not exist."),
+            Some("CMMVC5753E")
+        );
+        assert_eq!(
+            parse_error_code("rc=1:CMMVC6035E The action failed"),
+            Some("CMMVC6035E")
+        );
+        assert_eq!(parse_error_code("id 45 UPDATED"), None);
+        assert_eq!(parse_error_code("ABC12E"), None);
+    }
+
+    #[test]
+    fn created_ids_are_read_from_the_output() {
+        assert_eq!(
+            CommandOutput::success("User, id [46], successfully created").created_id(),
+            Some(46)
+        );
+        assert_eq!(CommandOutput::success("").created_id(), None);
+        assert_eq!(CommandOutput::success("id [x]").created_id(), None);
+        assert_eq!(
+            CommandOutput::success("User, role_id [5], id [46], successfully created").created_id(),
+            Some(46)
+        );
+        assert_eq!(
+            CommandOutput::success("User, role_id [5], successfully created").created_id(),
+            None
+        );
+    }
+
+    #[test]
+    fn outputs_with_error_codes_are_failures() {
+        assert!(CommandOutput::success("").is_success());
+        assert!(!CommandOutput::new(Some(0), "CMMVC5753E no such object", "").is_success());
+        assert_eq!(
+            CommandOutput::failure(1, "CMMVC5709E bad parameter").error_code,
+            Some(String::from("CMMVC5709E"))
+        );
+    }
+
+    #[test]
+    fn recording_executor_records_and_replays_outputs() {
+        let failure = CommandOutput::failure(1, "CMMVC5753E");
+        let mut executor = RecordingExecutor::new().respond_with(failure.clone());
+        let command = ModificationCommand::delete(&stored_user());
+
+        assert_eq!(executor.execute(&command).unwrap(), failure);
+        assert!(executor.execute(&command).unwrap().is_success());
+        assert_eq!(executor.commands(), &[command.clone(), command]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_process_executor_captures_output() {
+        let command = ModificationCommand::new(
+            CommandType::Modify,
+            "sh",
+            Some(String::from("45")),
+            vec![Parameter::Parameter(
+                "-c",
+                String::from("echo \"updating $0\"; echo CMMVC5753E missing >&2; exit 3"),
+            )],
+        );
+
+        let output = LocalProcessExecutor.execute(&command).unwrap();
+
+        assert_eq!(output.status, Some(3));
+        assert_eq!(output.stdout, "updating 45\n");
+        assert_eq!(output.error_code.as_deref(), Some("CMMVC5753E"));
+    }
+
+    #[test]
+    fn apply_commits_successful_changes() {
+        let mut user = stored_user();
+        *user.name = String::from("alice");
+        let mut executor = RecordingExecutor::new();
+
+        assert!(user.apply(&target(), &mut executor).unwrap().is_some());
+
+        assert_eq!(*user.name, "alice");
+        assert!(!user.is_changed());
+        assert_eq!(
+            executor.commands()[0].arguments(),
+            &[Parameter::Parameter("-set_name", String::from("alice"))]
+        );
+    }
+
+    #[test]
+    fn apply_rolls_back_failed_changes() {
+        let mut user = stored_user();
+        *user.name = String::from("alice");
+        let mut executor =
+            RecordingExecutor::new().respond_with(CommandOutput::failure(1, "CMMVC5753E"));
+
+        let error = user.apply(&target(), &mut executor).unwrap_err();
+
+        assert!(matches!(error, ApplyError::Failed(_)));
+        assert_eq!(*user.name, "bob");
+        assert!(!user.is_changed());
+    }
+
+    #[test]
+    fn apply_keeps_changes_it_cant_send() {
+        let mut user = stored_user();
+        *user.role_id = Ref::new(3);
+        let mut executor = RecordingExecutor::new();
+
+        let error = user
+            .apply(
+                &TargetSystem::new(SoftwareVersion::new(3, 188)),
+                &mut executor,
+            )
+            .unwrap_err();
+
+        assert!(matches!(error, ApplyError::Command(_)));
+        assert!(executor.commands().is_empty());
+        assert_eq!(*user.role_id, Ref::new(3));
+        assert!(user.is_changed());
+    }
+
+    #[test]
+    fn apply_skips_unchanged_objects() {
+        let mut user = stored_user();
+        let mut executor = RecordingExecutor::new();
+
+        assert!(user.apply(&target(), &mut executor).unwrap().is_none());
+        assert!(executor.commands().is_empty());
+    }
+}
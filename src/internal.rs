@@ -1,69 +1,232 @@
 use std::{
     cmp::Ordering,
-    default,
-    marker::PhantomData,
-    ops::{Deref, DerefMut}, any::Any,
+    error::Error,
+    fmt,
+    ops::{Deref, DerefMut, Not},
+    str::FromStr,
 };
 
-use bevy_reflect::{Reflect, Struct};
+use bevy_reflect::{reflect_trait, Reflect, Struct, TypeRegistry};
+use fancy_stuff_with_reflection_filter::{self as grammar, Comparison, Expression};
 use log::warn;
 
-use crate::SYSTEM_VERSION;
+use crate::executor::{ApplyError, CommandExecutor, CommandOutput};
+use crate::reference::ObjectReference;
 
-#[derive(Default, PartialEq, Eq, Debug, Clone, Copy, Reflect)]
+/// A software version of up to four components: `major.minor.patch.build`. Components
+/// that are not given are zero, so `8.5` and `8.5.0.0` are the same version.
+#[derive(Default, PartialEq, Eq, Hash, Debug, Clone, Copy, Reflect)]
 pub struct SoftwareVersion {
     major: u32,
     minor: u32,
+    patch: u32,
+    build: u32,
+}
+
+impl Ord for SoftwareVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.major
+            .cmp(&other.major)
+            .then(self.minor.cmp(&other.minor))
+            .then(self.patch.cmp(&other.patch))
+            .then(self.build.cmp(&other.build))
+    }
 }
 
 impl PartialOrd for SoftwareVersion {
-    fn ge(&self, other: &Self) -> bool {
-        self == other || self > other
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl SoftwareVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        SoftwareVersion {
+            major,
+            minor,
+            patch: 0,
+            build: 0,
+        }
     }
-    fn gt(&self, other: &Self) -> bool {
-        self.major > other.major || (self.major == other.major && self.minor > other.major)
+
+    /// Sets the patch level, e.g. the `0` in `8.5.0.3`.
+    pub const fn with_patch(mut self, patch: u32) -> Self {
+        self.patch = patch;
+        self
     }
-    fn le(&self, other: &Self) -> bool {
-        self == other || self < other
+
+    /// Sets the build or fix-pack level, e.g. the `3` in `8.5.0.3`.
+    pub const fn with_build(mut self, build: u32) -> Self {
+        self.build = build;
+        self
     }
-    fn lt(&self, other: &Self) -> bool {
-        self.major < other.major || (self.major == other.major && self.minor < other.minor)
+
+    pub const fn major(&self) -> u32 {
+        self.major
     }
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self > other {
-            Some(Ordering::Greater)
-        } else if self == other {
-            Some(Ordering::Equal)
-        } else {
-            Some(Ordering::Less)
+
+    pub const fn minor(&self) -> u32 {
+        self.minor
+    }
+
+    pub const fn patch(&self) -> u32 {
+        self.patch
+    }
+
+    pub const fn build(&self) -> u32 {
+        self.build
+    }
+
+    /// Parses a version such as `"4.12"`, `"8.5.0.3"` or `"v7.8.1"` at compile time,
+    /// panicking if it is malformed.
+    ///
+    /// ```
+    /// # use fancy_stuff_with_reflection::internal::SoftwareVersion;
+    /// const ROLE_ID_SINCE: SoftwareVersion = SoftwareVersion::parse("4.12");
+    /// ```
+    pub const fn parse(version: &str) -> Self {
+        match Self::try_parse(version) {
+            Ok(version) => version,
+            Err(ParseVersionError::Empty) => panic!("software version is empty"),
+            Err(ParseVersionError::InvalidComponent) => {
+                panic!("software version components must be numbers separated by '.'")
+            }
+            Err(ParseVersionError::Overflow) => panic!("software version component is too large"),
+            Err(ParseVersionError::TooManyComponents) => {
+                panic!("software version has more than four components")
+            }
+        }
+    }
+
+    /// Parses a version made of one to four dot-separated numbers, optionally prefixed
+    /// with `v`. Missing components are read as zero.
+    pub const fn try_parse(version: &str) -> Result<Self, ParseVersionError> {
+        let bytes = version.as_bytes();
+        let mut index = 0;
+        if !bytes.is_empty() && (bytes[0] == b'v' || bytes[0] == b'V') {
+            index = 1;
+        }
+        if index == bytes.len() {
+            return Err(ParseVersionError::Empty);
+        }
+
+        let mut components = [0u32; 4];
+        let mut component = 0;
+        let mut digits = 0;
+        while index < bytes.len() {
+            let byte = bytes[index];
+            if byte == b'.' {
+                if digits == 0 {
+                    return Err(ParseVersionError::InvalidComponent);
+                }
+                component += 1;
+                digits = 0;
+            } else if byte.is_ascii_digit() {
+                if component == components.len() {
+                    return Err(ParseVersionError::TooManyComponents);
+                }
+                let value = match components[component].checked_mul(10) {
+                    Some(value) => value.checked_add((byte - b'0') as u32),
+                    None => None,
+                };
+                components[component] = match value {
+                    Some(value) => value,
+                    None => return Err(ParseVersionError::Overflow),
+                };
+                digits += 1;
+            } else {
+                return Err(ParseVersionError::InvalidComponent);
+            }
+            index += 1;
         }
+        if digits == 0 {
+            return Err(ParseVersionError::InvalidComponent);
+        }
+
+        Ok(SoftwareVersion::new(components[0], components[1])
+            .with_patch(components[2])
+            .with_build(components[3]))
     }
 }
 
-impl SoftwareVersion {
-    pub const fn new(major: u32, minor: u32) -> Self {
-        SoftwareVersion { major, minor }
+/// Writes `major.minor`, followed by the patch and build levels only when they are set.
+impl fmt::Display for SoftwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.patch != 0 || self.build != 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        if self.build != 0 {
+            write!(f, ".{}", self.build)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SoftwareVersion {
+    type Err = ParseVersionError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        Self::try_parse(version.trim())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseVersionError {
+    Empty,
+    /// A component was empty or contained something other than digits.
+    InvalidComponent,
+    Overflow,
+    TooManyComponents,
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseVersionError::Empty => write!(f, "software version is empty"),
+            ParseVersionError::InvalidComponent => write!(
+                f,
+                "software version components must be numbers separated by '.'"
+            ),
+            ParseVersionError::Overflow => write!(f, "software version component is too large"),
+            ParseVersionError::TooManyComponents => {
+                write!(f, "software version has more than four components")
+            }
+        }
     }
 }
 
-pub trait FieldDataType = Default + Clone + Reflect;
-pub trait FieldEnumType = Default + Clone + Reflect;
+impl Error for ParseVersionError {}
+
+/// The value types a [`Field`] can hold. Serializing a field with the `serde` feature
+/// additionally needs its value to implement serde's traits.
+pub trait FieldDataType =
+    Default + Clone + PartialEq + Reflect + IsEmpty + FormatArgument + ParseArgument + AsReference;
+pub trait FieldEnumType = FieldParameter;
 
 #[derive(Default, Reflect, Clone)]
 pub struct FieldInner<InnerType: FieldDataType, FieldEnum: FieldEnumType> {
-    min_version: Option<SoftwareVersion>,
-    max_version: Option<SoftwareVersion>,
     field_name: String,
     field_enum: FieldEnum,
     value: InnerType,
     old_value: Option<InnerType>,
 }
 
-#[derive(Reflect, Clone)]
+/// The software versions that support a field. Filters can be combined with
+/// [`VersionFilter::and`], [`VersionFilter::or`] and `!`, or parsed from expressions such
+/// as `">=4.12, <5.0 || =3.188"`, where `,` binds tighter than `||`.
+#[derive(Reflect, Debug, Clone, PartialEq, Eq)]
 pub enum VersionFilter {
     MinVersion(SoftwareVersion),
     MaxVersion(SoftwareVersion),
     VersionRange(SoftwareVersion, SoftwareVersion),
+    Above(SoftwareVersion),
+    Below(SoftwareVersion),
+    Exactly(SoftwareVersion),
+    Excluding(SoftwareVersion),
+    All(Vec<VersionFilter>),
+    Any(Vec<VersionFilter>),
+    Not(Box<VersionFilter>),
 }
 
 impl VersionFilter {
@@ -78,9 +241,185 @@ impl VersionFilter {
     pub fn version_range(min_version: SoftwareVersion, max_version: SoftwareVersion) -> Self {
         VersionFilter::VersionRange(min_version, max_version)
     }
+
+    pub fn above(version: SoftwareVersion) -> Self {
+        VersionFilter::Above(version)
+    }
+
+    pub fn below(version: SoftwareVersion) -> Self {
+        VersionFilter::Below(version)
+    }
+
+    pub fn exactly(version: SoftwareVersion) -> Self {
+        VersionFilter::Exactly(version)
+    }
+
+    /// Matches every version except `version`, e.g. a release known to be broken.
+    pub fn excluding(version: SoftwareVersion) -> Self {
+        VersionFilter::Excluding(version)
+    }
+
+    /// Matches versions accepted by both filters.
+    pub fn and(self, other: VersionFilter) -> Self {
+        match self {
+            VersionFilter::All(mut filters) => {
+                filters.push(other);
+                VersionFilter::All(filters)
+            }
+            filter => VersionFilter::All(vec![filter, other]),
+        }
+    }
+
+    /// Matches versions accepted by either filter.
+    pub fn or(self, other: VersionFilter) -> Self {
+        match self {
+            VersionFilter::Any(mut filters) => {
+                filters.push(other);
+                VersionFilter::Any(filters)
+            }
+            filter => VersionFilter::Any(vec![filter, other]),
+        }
+    }
+
+    /// Whether a system running `version` supports the filtered field. Minimum, maximum
+    /// and range bounds are inclusive.
+    pub fn matches(&self, version: SoftwareVersion) -> bool {
+        match self {
+            VersionFilter::MinVersion(min_version) => version >= *min_version,
+            VersionFilter::MaxVersion(max_version) => version <= *max_version,
+            VersionFilter::VersionRange(min_version, max_version) => {
+                version >= *min_version && version <= *max_version
+            }
+            VersionFilter::Above(other) => version > *other,
+            VersionFilter::Below(other) => version < *other,
+            VersionFilter::Exactly(other) => version == *other,
+            VersionFilter::Excluding(other) => version != *other,
+            VersionFilter::All(filters) => filters.iter().all(|filter| filter.matches(version)),
+            VersionFilter::Any(filters) => filters.iter().any(|filter| filter.matches(version)),
+            VersionFilter::Not(filter) => !filter.matches(version),
+        }
+    }
+}
+
+impl Not for VersionFilter {
+    type Output = VersionFilter;
+
+    fn not(self) -> Self::Output {
+        match self {
+            VersionFilter::Not(filter) => *filter,
+            filter => VersionFilter::Not(Box::new(filter)),
+        }
+    }
+}
+
+/// Writes the filter in the expression syntax accepted by [`VersionFilter::from_str`].
+impl fmt::Display for VersionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionFilter::MinVersion(version) => write!(f, ">={}", version),
+            VersionFilter::MaxVersion(version) => write!(f, "<={}", version),
+            VersionFilter::VersionRange(min_version, max_version) => {
+                write!(f, ">={}, <={}", min_version, max_version)
+            }
+            VersionFilter::Above(version) => write!(f, ">{}", version),
+            VersionFilter::Below(version) => write!(f, "<{}", version),
+            VersionFilter::Exactly(version) => write!(f, "={}", version),
+            VersionFilter::Excluding(version) => write!(f, "!={}", version),
+            VersionFilter::All(filters) => {
+                for (index, filter) in filters.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match filter {
+                        VersionFilter::Any(_) => write!(f, "({})", filter)?,
+                        filter => write!(f, "{}", filter)?,
+                    }
+                }
+                Ok(())
+            }
+            VersionFilter::Any(filters) => {
+                for (index, filter) in filters.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " || ")?;
+                    }
+                    write!(f, "{}", filter)?;
+                }
+                Ok(())
+            }
+            VersionFilter::Not(filter) => write!(f, "!({})", filter),
+        }
+    }
+}
+
+impl FromStr for VersionFilter {
+    type Err = ParseFilterError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        VersionFilter::from_expression(grammar::parse(expression)?)
+    }
+}
+
+impl VersionFilter {
+    fn from_expression(expression: Expression) -> Result<Self, ParseFilterError> {
+        Ok(match expression {
+            Expression::Comparison(comparison, version) => {
+                let version = version
+                    .parse()
+                    .map_err(|error| ParseFilterError::Version(version, error))?;
+                match comparison {
+                    Comparison::AtLeast => VersionFilter::MinVersion(version),
+                    Comparison::AtMost => VersionFilter::MaxVersion(version),
+                    Comparison::Above => VersionFilter::Above(version),
+                    Comparison::Below => VersionFilter::Below(version),
+                    Comparison::Exactly => VersionFilter::Exactly(version),
+                    Comparison::Excluding => VersionFilter::Excluding(version),
+                }
+            }
+            Expression::All(left, right) => {
+                VersionFilter::from_expression(*left)?.and(VersionFilter::from_expression(*right)?)
+            }
+            Expression::Any(left, right) => {
+                VersionFilter::from_expression(*left)?.or(VersionFilter::from_expression(*right)?)
+            }
+            Expression::Not(filter) => !VersionFilter::from_expression(*filter)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseFilterError {
+    Version(String, ParseVersionError),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+}
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseFilterError::Version(version, error) => {
+                write!(f, "invalid version `{}` in filter: {}", version, error)
+            }
+            ParseFilterError::UnexpectedToken(token) => {
+                write!(f, "unexpected `{}` in version filter", token)
+            }
+            ParseFilterError::UnexpectedEnd => write!(f, "version filter ended unexpectedly"),
+        }
+    }
+}
+
+impl Error for ParseFilterError {}
+
+impl From<grammar::ParseError> for ParseFilterError {
+    fn from(error: grammar::ParseError) -> Self {
+        match error {
+            grammar::ParseError::UnexpectedToken(token) => ParseFilterError::UnexpectedToken(token),
+            grammar::ParseError::UnexpectedEnd => ParseFilterError::UnexpectedEnd,
+        }
+    }
 }
 
 #[derive(Reflect, Clone)]
+#[reflect_value(TrackedField)]
 pub enum Field<T: FieldDataType, FieldEnum: FieldEnumType> {
     Field(FieldInner<T, FieldEnum>),
     VersionedField(FieldInner<T, FieldEnum>, VersionFilter),
@@ -94,37 +433,137 @@ impl<T: FieldDataType, FieldEnum: FieldEnumType> Field<T, FieldEnum> {
     pub fn new_versioned(field_name: impl Into<String>, field: FieldEnum, versions: VersionFilter) -> Self {
         Field::VersionedField(FieldInner::new(field_name, field), versions)
     }
+
+    pub fn inner(&self) -> &FieldInner<T, FieldEnum> {
+        match self {
+            Field::Field(inner) => inner,
+            Field::VersionedField(inner, _) => inner,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut FieldInner<T, FieldEnum> {
+        match self {
+            Field::Field(inner) => inner,
+            Field::VersionedField(inner, _) => inner,
+        }
+    }
+
+    pub fn version_filter(&self) -> Option<&VersionFilter> {
+        match self {
+            Field::Field(_) => None,
+            Field::VersionedField(_, versions) => Some(versions),
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        self.inner().get()
+    }
+
+    /// See [`FieldInner::set`].
+    pub fn set(&mut self, value: T) {
+        self.inner_mut().set(value);
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.inner().is_changed()
+    }
+
+    /// The value before the field was first modified, if it has been since the last commit.
+    pub fn baseline_value(&self) -> Option<&T> {
+        self.inner().old_value.as_ref()
+    }
+
+    /// Replaces the value and baseline outright, e.g. when loading a stored object.
+    pub fn restore(&mut self, value: T, baseline: Option<T>) {
+        let inner = self.inner_mut();
+        inner.value = value;
+        inner.old_value = baseline;
+    }
+
+    /// See [`FieldInner::commit`].
+    pub fn commit(&mut self) {
+        self.inner_mut().commit();
+    }
+
+    /// See [`FieldInner::rollback`].
+    pub fn rollback(&mut self) {
+        self.inner_mut().rollback();
+    }
 }
 
 impl<T: FieldDataType, FieldEnum: FieldEnumType> FieldInner<T, FieldEnum> {
     pub fn new(field_name: impl Into<String>, field: FieldEnum) -> Self {
         FieldInner {
             field_name: field_name.into(),
-            min_version: None,
-            max_version: None,
             value: T::default(),
             old_value: None,
             field_enum: field
         }
     }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Replaces the value, recording the current one as the baseline if there isn't one
+    /// yet. Setting a field to the value it already holds does nothing.
+    pub fn set(&mut self, value: T) {
+        if value == self.value {
+            return;
+        }
+        if self.old_value.is_none() {
+            self.old_value = Some(self.value.clone());
+        }
+        self.value = value;
+    }
+
+    /// Whether the value differs from its baseline. Borrowing the value mutably, or
+    /// setting it back to the baseline, does not count as a change.
+    pub fn is_changed(&self) -> bool {
+        self.old_value
+            .as_ref()
+            .is_some_and(|old_value| *old_value != self.value)
+    }
+
+    /// Makes the current value the baseline, so the field is no longer changed.
+    pub fn commit(&mut self) {
+        self.old_value = None;
+    }
+
+    /// Restores the baseline value, discarding any change since the last commit.
+    pub fn rollback(&mut self) {
+        if let Some(old_value) = self.old_value.take() {
+            self.value = old_value;
+        }
+    }
+
+    /// Returns a copy of this field holding the value it had before it was first modified.
+    fn baseline(&self) -> Self {
+        let mut baseline = self.clone();
+        if let Some(old_value) = &self.old_value {
+            baseline.value = old_value.clone();
+        }
+        baseline
+    }
+
+    /// Returns a copy of this field holding the default value of its type.
+    fn with_default_value(&self) -> Self {
+        let mut default = self.clone();
+        default.value = T::default();
+        default
+    }
 }
 
 impl<T: FieldDataType, FieldEnum: FieldEnumType> Deref for Field<T, FieldEnum> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        match self {
-            Field::Field(inner) => inner,
-            Field::VersionedField(inner, _) => inner,
-        }
+        self.inner()
     }
 }
 
 impl<T: FieldDataType, FieldEnum: FieldEnumType> DerefMut for Field<T, FieldEnum> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        match self {
-            Field::Field(inner) => inner,
-            Field::VersionedField(inner, _) => inner,
-        }
+        self.inner_mut()
     }
 }
 
@@ -140,62 +579,467 @@ impl<T: FieldDataType, FieldEnum: FieldEnumType> DerefMut for FieldInner<T, Fiel
         if self.old_value.is_none() {
             self.old_value = Some(self.value.clone());
         }
-        if let Some(min_version) = self.min_version {
-            if SYSTEM_VERSION < min_version {
-                warn!(
-                    "Field not supported on {:?}, min version is {:?}",
-                    SYSTEM_VERSION, min_version
-                );
-            }
+        &mut self.value
+    }
+}
+
+/// What to do with a changed field whose [`VersionFilter`] rejects the target version.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VersionPolicy {
+    /// Refuse to build the command.
+    #[default]
+    Error,
+    /// Leave the field's parameter out of the command.
+    Drop,
+    /// Log a warning and include the parameter anyway.
+    Warn,
+}
+
+/// The system that commands are generated for. The same object can be turned into
+/// commands for several targets running different software versions.
+#[derive(Debug, Clone)]
+pub struct TargetSystem {
+    version: SoftwareVersion,
+    version_policy: VersionPolicy,
+}
+
+impl TargetSystem {
+    pub fn new(version: SoftwareVersion) -> Self {
+        TargetSystem {
+            version,
+            version_policy: VersionPolicy::default(),
         }
+    }
+
+    pub fn with_version_policy(mut self, version_policy: VersionPolicy) -> Self {
+        self.version_policy = version_policy;
+        self
+    }
+
+    pub fn version(&self) -> SoftwareVersion {
+        self.version
+    }
+
+    pub fn version_policy(&self) -> VersionPolicy {
+        self.version_policy
+    }
 
-        if let Some(max_version) = self.max_version {
-            if SYSTEM_VERSION > max_version {
+    /// How this target treats `field`, or `None` if the field has no version filter.
+    pub fn version_decision(&self, field: &dyn TrackedField) -> Option<VersionDecision> {
+        let filter = field.version_filter()?;
+        let outcome = match self.version_policy {
+            _ if filter.matches(self.version) => VersionOutcome::Supported,
+            VersionPolicy::Error => VersionOutcome::Rejected,
+            VersionPolicy::Drop => VersionOutcome::Dropped,
+            VersionPolicy::Warn => VersionOutcome::Warned,
+        };
+        Some(VersionDecision {
+            field: String::from(field.field_name()),
+            filter: filter.clone(),
+            version: self.version,
+            outcome,
+        })
+    }
+
+    /// Decides whether `field` belongs in a command for this target, according to its
+    /// version filter and the target's [`VersionPolicy`].
+    fn includes(&self, field: &dyn TrackedField) -> Result<bool, CommandError> {
+        let Some(decision) = self.version_decision(field) else {
+            return Ok(true);
+        };
+
+        match decision.outcome {
+            VersionOutcome::Supported => Ok(true),
+            VersionOutcome::Rejected => Err(CommandError::UnsupportedField {
+                field: decision.field,
+                version: decision.version,
+                filter: decision.filter,
+            }),
+            VersionOutcome::Dropped => Ok(false),
+            VersionOutcome::Warned => {
                 warn!(
-                    "Field not supported on {:?}, max version is {:?}",
-                    SYSTEM_VERSION, max_version
+                    "Field {} not supported on {}, requires {}",
+                    decision.field, decision.version, decision.filter
                 );
+                Ok(true)
             }
         }
+    }
+}
 
-        &mut self.value
+/// The outcome of checking a field's [`VersionFilter`] against a [`TargetSystem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VersionDecision {
+    pub field: String,
+    pub filter: VersionFilter,
+    pub version: SoftwareVersion,
+    pub outcome: VersionOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum VersionOutcome {
+    /// The filter accepts the target's version.
+    Supported,
+    /// Unsupported; [`VersionPolicy::Error`] refuses to build the command.
+    Rejected,
+    /// Unsupported; [`VersionPolicy::Drop`] leaves the parameter out.
+    Dropped,
+    /// Unsupported; [`VersionPolicy::Warn`] includes the parameter anyway.
+    Warned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// A changed field is not supported by the target's software version.
+    UnsupportedField {
+        field: String,
+        version: SoftwareVersion,
+        filter: VersionFilter,
+    },
+    /// A command has to create an object under a known id, but the create command leaves
+    /// it to the target system: the object type's create command can't set the id, see
+    /// [`SystemObject::id_switch`], or the id is the default.
+    IdNotSettable {
+        object_type: &'static str,
+        object_id: u32,
+    },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnsupportedField {
+                field,
+                version,
+                filter,
+            } => write!(
+                f,
+                "field {} is not supported on {}, requires {}",
+                field, version, filter
+            ),
+            CommandError::IdNotSettable {
+                object_type,
+                object_id,
+            } => write!(
+                f,
+                "create commands for {} can't set the object id {}",
+                object_type, object_id
+            ),
+        }
     }
 }
 
+impl Error for CommandError {}
+
 pub trait IdentifiableObject {
     fn get_id(&self) -> u32;
 }
 
 pub trait ModifiableObject: IdentifiableObject {
     fn get_modify_command(&self) -> &'static str;
+
+    /// Generates the modify command for the changed fields and runs it. On success the
+    /// changes are committed; if the command fails or can't be run they are rolled back,
+    /// so the object matches the target again. If no command can be generated, e.g. the
+    /// target doesn't support a changed field, nothing runs and the changes are kept.
+    /// Returns `None` without running anything if no field has changed.
+    fn apply(
+        &mut self,
+        target: &TargetSystem,
+        executor: &mut impl CommandExecutor,
+    ) -> Result<Option<CommandOutput>, ApplyError>
+    where
+        Self: SystemObject + Sized,
+    {
+        if !self.is_changed() {
+            return Ok(None);
+        }
+        let command = ModificationCommand::modify(self, target)?;
+
+        match executor.execute(&command) {
+            Ok(output) if output.is_success() => {
+                self.commit();
+                Ok(Some(output))
+            }
+            Ok(output) => {
+                self.rollback();
+                Err(ApplyError::Failed(output))
+            }
+            Err(error) => {
+                self.rollback();
+                Err(ApplyError::Io(error))
+            }
+        }
+    }
+}
+
+pub trait CreatableObject: IdentifiableObject {
+    fn get_create_command(&self) -> &'static str;
+}
+
+pub trait DeletableObject: IdentifiableObject {
+    fn get_delete_command(&self) -> &'static str;
+}
+
+/// An object whose members are [`Field`]s that can be walked through reflection.
+pub trait SystemObject: Struct {
+    type FieldEnum: FieldParameter;
+
+    /// Registers every `Field<T, Self::FieldEnum>` type used by this object, so that its
+    /// members can be cast to [`TrackedField`] while walking the reflected struct.
+    fn register_fields(registry: &mut TypeRegistry);
+
+    /// The command that performs `command_type` on this type of object, if it supports it.
+    fn command_name(command_type: CommandType) -> Option<&'static str>;
+
+    /// Whether any field differs from its baseline.
+    fn is_changed(&self) -> bool
+    where
+        Self: Sized,
+    {
+        tracked_fields(self).iter().any(|field| field.is_changed())
+    }
+
+    /// Accepts every field's current value as its new baseline, e.g. once the target
+    /// system has applied the command built from them.
+    fn commit(&mut self)
+    where
+        Self: Sized,
+    {
+        for_each_tracked_field_mut(self, |field| field.commit());
+    }
+
+    /// Restores every field to its baseline, discarding the changes made since the last
+    /// commit.
+    fn rollback(&mut self)
+    where
+        Self: Sized,
+    {
+        for_each_tracked_field_mut(self, |field| field.rollback());
+    }
+
+    /// The objects that the fields' current values refer to, in field order.
+    fn references(&self) -> Vec<ObjectReference>
+    where
+        Self: Sized,
+    {
+        tracked_fields(self)
+            .iter()
+            .filter_map(|field| field.reference())
+            .collect()
+    }
+
+    /// The switch that sets the object id in commands of `command_type`, if they can.
+    fn id_switch(&self, command_type: CommandType) -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        tracked_fields(self)
+            .into_iter()
+            .find(|field| field.is_identifier())
+            .and_then(|field| field.switches(command_type).param)
+    }
+
+    /// The field that `switch` sets or resets in commands of `command_type`.
+    fn field_for_switch(&self, command_type: CommandType, switch: &str) -> Option<&dyn TrackedField>
+    where
+        Self: Sized,
+    {
+        tracked_fields(self).into_iter().find(|field| {
+            let switches = field.switches(command_type);
+            switches.param == Some(switch) || switches.reset_flag == Some(switch)
+        })
+    }
+
+    /// Sets the field that `parameter` belongs to, the inverse of building a command:
+    /// a value switch sets the field from its argument, and a reset flag empties it.
+    fn apply_parameter(
+        &mut self,
+        command_type: CommandType,
+        parameter: &Parameter,
+    ) -> Result<(), ParameterError>
+    where
+        Self: Sized,
+    {
+        let mut result = Err(ParameterError::Unknown(String::from(parameter.name())));
+        for_each_tracked_field_mut(self, |field| {
+            let switches = field.switches(command_type);
+            let name = parameter.name();
+            result = match parameter {
+                Parameter::Parameter(_, value) if switches.param == Some(name) => field
+                    .set_argument(value)
+                    .map_err(|error| ParameterError::InvalidValue(String::from(name), error)),
+                Parameter::Flag(_) if switches.reset_flag == Some(name) => {
+                    field.reset();
+                    Ok(())
+                }
+                Parameter::Flag(_) if switches.param == Some(name) => {
+                    Err(ParameterError::MissingValue(String::from(name)))
+                }
+                Parameter::Parameter(..) if switches.reset_flag == Some(name) => {
+                    Err(ParameterError::UnexpectedValue(String::from(name)))
+                }
+                _ => return,
+            };
+        });
+        result
+    }
+
+    /// Sets the field whose [`TrackedField::field_name`] is `field_name` from text, as the
+    /// target system lists it. An empty value resets the field to its default. Returns
+    /// whether a field has that name.
+    fn set_named_field(&mut self, field_name: &str, value: &str) -> Result<bool, ParseArgumentError>
+    where
+        Self: Sized,
+    {
+        let mut result = Ok(false);
+        for_each_tracked_field_mut(self, |field| {
+            if field.field_name() != field_name {
+                return;
+            }
+            result = if value.is_empty() {
+                field.reset();
+                Ok(true)
+            } else {
+                field.set_argument(value).map(|()| true)
+            };
+        });
+        result
+    }
+
+    /// Sets the identifier field, e.g. once the target system has assigned an id.
+    fn set_id(&mut self, id: u32)
+    where
+        Self: Sized,
+    {
+        for_each_tracked_field_mut(self, |field| {
+            if field.is_identifier() {
+                field
+                    .set_argument(&id.to_string())
+                    .expect("identifiers hold numeric ids");
+            }
+        });
+    }
 }
 
-trait SystemObject: PartialEq {
-    type field_enum: FieldParameter;
+/// Why a command parameter could not be applied to an object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParameterError {
+    /// No field uses the switch.
+    Unknown(String),
+    /// The switch takes a value but was given none.
+    MissingValue(String),
+    /// The switch is a flag but was given a value.
+    UnexpectedValue(String),
+    InvalidValue(String, ParseArgumentError),
+}
 
-    fn eq(&self, rhs: &Self) -> bool {
-        PartialEq::eq(self, &rhs)
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterError::Unknown(name) => write!(f, "unknown parameter {}", name),
+            ParameterError::MissingValue(name) => write!(f, "parameter {} needs a value", name),
+            ParameterError::UnexpectedValue(name) => {
+                write!(f, "parameter {} doesn't take a value", name)
+            }
+            ParameterError::InvalidValue(name, error) => {
+                write!(f, "invalid value for {}: {}", name, error)
+            }
+        }
     }
 }
 
+impl Error for ParameterError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum CommandType {
     Create,
     Modify,
     Delete,
 }
 
+/// A single argument of a command: either a switch followed by its rendered value, or a
+/// switch on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Parameter {
-    Parameter(&'static str),
+    Parameter(&'static str, String),
     Flag(&'static str),
 }
 
+impl Parameter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::Parameter(name, _) => name,
+            Parameter::Flag(name) => name,
+        }
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        match self {
+            Parameter::Parameter(_, value) => Some(value),
+            Parameter::Flag(_) => None,
+        }
+    }
+}
+
 pub trait FieldParameter: Default + Reflect + Clone {
-    fn get_parameter<FieldType: Default + IsEmpty + Reflect + Clone + Reflect>(
+    fn get_parameter<FieldType: FieldDataType>(
         &self,
         command_type: CommandType,
         old_value: &FieldInner<FieldType, Self>,
         new_value: &FieldInner<FieldType, Self>,
     ) -> Parameter;
+
+    /// Renders the value of a field as a command argument. Override this to change how
+    /// particular fields are written; the default uses the value's [`FormatArgument`] impl.
+    fn format_value<FieldType: FieldDataType>(
+        &self,
+        field: &FieldInner<FieldType, Self>,
+    ) -> String {
+        field.value.format_argument()
+    }
+
+    /// Reads a field value back from a command argument, the inverse of
+    /// [`FieldParameter::format_value`]. The default uses the type's [`ParseArgument`]
+    /// impl.
+    fn parse_value<FieldType: FieldDataType>(
+        &self,
+        argument: &str,
+    ) -> Result<FieldType, ParseArgumentError> {
+        FieldType::parse_argument(argument)
+    }
+
+    /// The switches that [`FieldParameter::get_parameter`] produces for this field, so
+    /// that commands can be mapped back onto fields. The default knows of none.
+    fn switches(&self, _command_type: CommandType) -> FieldSwitches {
+        FieldSwitches::default()
+    }
+
+    /// Whether this field identifies the object rather than describing it. Identifier
+    /// fields are never turned into parameters; the id is passed to the command instead.
+    fn is_identifier(&self) -> bool {
+        false
+    }
+}
+
+/// The switches a field is written with in one type of command.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FieldSwitches {
+    /// The switch that takes the field's new value.
+    pub param: Option<&'static str>,
+    /// The flag that empties the field.
+    pub reset_flag: Option<&'static str>,
 }
 
 pub trait IsEmpty {
@@ -208,41 +1052,735 @@ impl IsEmpty for String {
     }
 }
 
+macro_rules! impl_never_empty {
+    ($($ty:ty),*) => {
+        $(
+            impl IsEmpty for $ty {
+                fn is_empty(&self) -> bool {
+                    false
+                }
+            }
+        )*
+    };
+}
+
+impl_never_empty!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool, char);
+
+/// Renders a field value the way it should appear on a command line, before any quoting.
+pub trait FormatArgument {
+    fn format_argument(&self) -> String;
+}
+
+impl FormatArgument for String {
+    fn format_argument(&self) -> String {
+        self.clone()
+    }
+}
+
+macro_rules! impl_format_argument_display {
+    ($($ty:ty),*) => {
+        $(
+            impl FormatArgument for $ty {
+                fn format_argument(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_format_argument_display!(
+    u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool, char
+);
+
+/// Reads a field value from a command argument, the inverse of [`FormatArgument`].
+pub trait ParseArgument: Sized {
+    fn parse_argument(argument: &str) -> Result<Self, ParseArgumentError>;
+}
+
+impl ParseArgument for String {
+    fn parse_argument(argument: &str) -> Result<Self, ParseArgumentError> {
+        Ok(String::from(argument))
+    }
+}
+
+macro_rules! impl_parse_argument_from_str {
+    ($($ty:ty),*) => {
+        $(
+            impl ParseArgument for $ty {
+                fn parse_argument(argument: &str) -> Result<Self, ParseArgumentError> {
+                    argument.parse().map_err(|_| ParseArgumentError {
+                        argument: String::from(argument),
+                        expected: stringify!($ty),
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_parse_argument_from_str!(
+    u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool, char
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseArgumentError {
+    pub argument: String,
+    /// The type the argument should have been.
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ParseArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is not a valid {}", self.argument, self.expected)
+    }
+}
+
+impl Error for ParseArgumentError {}
+
+/// The object a field value refers to, if any. Only [`Ref`](crate::reference::Ref) values
+/// refer to objects.
+pub trait AsReference {
+    fn as_reference(&self) -> Option<ObjectReference> {
+        None
+    }
+}
+
+impl AsReference for String {}
+
+macro_rules! impl_no_reference {
+    ($($ty:ty),*) => {
+        $(
+            impl AsReference for $ty {}
+        )*
+    };
+}
+
+impl_no_reference!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool, char);
+
+/// Type-erased view of a [`Field`], used to inspect the members of a reflected object
+/// without knowing their value types. Obtained from a `&dyn Reflect` through
+/// [`ReflectTrackedField`].
+#[reflect_trait]
+pub trait TrackedField {
+    fn field_name(&self) -> &str;
+    fn is_identifier(&self) -> bool;
+    fn is_changed(&self) -> bool;
+    fn is_default(&self) -> bool;
+    fn get_parameter(&self, command_type: CommandType) -> Parameter;
+    fn version_filter(&self) -> Option<&VersionFilter>;
+    fn commit(&mut self);
+    fn rollback(&mut self);
+    /// The current value, rendered the way it would appear in a command.
+    fn formatted_value(&self) -> String;
+    /// Whether this field's value differs from `other`'s, which should be the same member
+    /// of another instance of the object.
+    fn differs_from(&self, other: &dyn Reflect) -> bool;
+    /// Like [`TrackedField::get_parameter`], but compares against `baseline`, the same
+    /// member of another instance, instead of this field's own baseline.
+    fn get_parameter_from(&self, baseline: &dyn Reflect, command_type: CommandType) -> Parameter;
+    /// Copies the value and baseline of `other`, which must be a field of the same type.
+    /// Returns whether it was.
+    fn restore_from(&mut self, other: &dyn Reflect) -> bool;
+    fn switches(&self, command_type: CommandType) -> FieldSwitches;
+    /// Sets the value from a command argument, see [`FieldParameter::parse_value`].
+    fn set_argument(&mut self, argument: &str) -> Result<(), ParseArgumentError>;
+    /// Sets the value to its type's default, as a reset flag does.
+    fn reset(&mut self);
+    /// The object the current value refers to, see [`Ref`](crate::reference::Ref).
+    fn reference(&self) -> Option<ObjectReference>;
+}
+
+impl<T: FieldDataType, FieldEnum: FieldEnumType> TrackedField for Field<T, FieldEnum> {
+    fn field_name(&self) -> &str {
+        &self.inner().field_name
+    }
+
+    fn is_identifier(&self) -> bool {
+        self.inner().field_enum.is_identifier()
+    }
+
+    fn is_changed(&self) -> bool {
+        self.inner().is_changed()
+    }
+
+    fn is_default(&self) -> bool {
+        self.inner().value == T::default()
+    }
+
+    /// Created objects have no previous state, so for [`CommandType::Create`] the field is
+    /// compared against its default value rather than its baseline.
+    fn get_parameter(&self, command_type: CommandType) -> Parameter {
+        let inner = self.inner();
+        let old_value = match command_type {
+            CommandType::Create => inner.with_default_value(),
+            CommandType::Modify | CommandType::Delete => inner.baseline(),
+        };
+        inner
+            .field_enum
+            .get_parameter(command_type, &old_value, inner)
+    }
+
+    fn version_filter(&self) -> Option<&VersionFilter> {
+        Field::version_filter(self)
+    }
+
+    fn commit(&mut self) {
+        Field::commit(self);
+    }
+
+    fn rollback(&mut self) {
+        Field::rollback(self);
+    }
+
+    fn formatted_value(&self) -> String {
+        let inner = self.inner();
+        inner.field_enum.format_value(inner)
+    }
+
+    fn differs_from(&self, other: &dyn Reflect) -> bool {
+        match other.downcast_ref::<Self>() {
+            Some(other) => other.get() != self.get(),
+            None => true,
+        }
+    }
+
+    fn get_parameter_from(&self, baseline: &dyn Reflect, command_type: CommandType) -> Parameter {
+        match baseline.downcast_ref::<Self>() {
+            Some(baseline) => {
+                let inner = self.inner();
+                inner
+                    .field_enum
+                    .get_parameter(command_type, baseline.inner(), inner)
+            }
+            None => self.get_parameter(command_type),
+        }
+    }
+
+    fn restore_from(&mut self, other: &dyn Reflect) -> bool {
+        match other.downcast_ref::<Self>() {
+            Some(other) => {
+                self.restore(other.get().clone(), other.baseline_value().cloned());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn switches(&self, command_type: CommandType) -> FieldSwitches {
+        self.inner().field_enum.switches(command_type)
+    }
+
+    fn set_argument(&mut self, argument: &str) -> Result<(), ParseArgumentError> {
+        let value = self.inner().field_enum.parse_value(argument)?;
+        self.set(value);
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.set(T::default());
+    }
+
+    fn reference(&self) -> Option<ObjectReference> {
+        self.get().as_reference()
+    }
+}
+
+/// The name of a type without its module path, e.g. `User`.
+pub(crate) fn short_type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+fn field_registry<ObjectType: SystemObject>() -> TypeRegistry {
+    let mut registry = TypeRegistry::empty();
+    ObjectType::register_fields(&mut registry);
+    registry
+}
+
+fn as_tracked<'a>(registry: &TypeRegistry, field: &'a dyn Reflect) -> Option<&'a dyn TrackedField> {
+    registry
+        .get_type_data::<ReflectTrackedField>(field.as_any().type_id())
+        .and_then(|tracked| tracked.get(field))
+}
+
+/// Collects the members of `object` that are registered [`Field`]s. Members that are
+/// not fields are skipped.
+fn tracked_fields<ObjectType: SystemObject>(object: &ObjectType) -> Vec<&dyn TrackedField> {
+    let registry = field_registry::<ObjectType>();
+
+    object
+        .iter_fields()
+        .filter_map(|field| as_tracked(&registry, field))
+        .collect()
+}
+
+/// Calls `action` on each member of `object` that is a registered [`Field`].
+fn for_each_tracked_field_mut<ObjectType: SystemObject>(
+    object: &mut ObjectType,
+    mut action: impl FnMut(&mut dyn TrackedField),
+) {
+    let registry = field_registry::<ObjectType>();
+
+    for index in 0..object.field_len() {
+        let Some(field) = object.field_at_mut(index) else {
+            continue;
+        };
+        let tracked = registry.get_type_data::<ReflectTrackedField>(field.as_any().type_id());
+        if let Some(field) = tracked.and_then(|tracked| tracked.get_mut(field)) {
+            action(field);
+        }
+    }
+}
+
+/// Keeps the parameters of the fields that `target` supports.
+fn collect_arguments<'a>(
+    fields: impl Iterator<Item = (&'a dyn TrackedField, Parameter)>,
+    target: &TargetSystem,
+) -> Result<Vec<Parameter>, CommandError> {
+    let mut arguments = Vec::new();
+    for (field, parameter) in fields {
+        if target.includes(field)? {
+            arguments.push(parameter);
+        }
+    }
+    Ok(arguments)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ModificationCommand {
+    command_type: CommandType,
+    command: String,
+    object_id: Option<String>,
+    arguments: Vec<Parameter>,
+}
+
+impl ModificationCommand {
+    /// Assembles a command by hand. Prefer [`ModificationCommand::modify`] and friends,
+    /// which build the arguments from an object.
+    pub fn new(
+        command_type: CommandType,
+        command: impl Into<String>,
+        object_id: Option<String>,
+        arguments: Vec<Parameter>,
+    ) -> Self {
+        ModificationCommand {
+            command_type,
+            command: command.into(),
+            object_id,
+            arguments,
+        }
+    }
+
+    /// Builds the modify command for `object` from the fields that have been changed
+    /// since it was created.
+    pub fn modify<ObjectType: ModifiableObject + SystemObject>(
+        object: &ObjectType,
+        target: &TargetSystem,
+    ) -> Result<Self, CommandError> {
+        let fields = tracked_fields(object)
+            .into_iter()
+            .filter(|field| !field.is_identifier() && field.is_changed())
+            .map(|field| (field, field.get_parameter(CommandType::Modify)));
+
+        Ok(ModificationCommand {
+            command_type: CommandType::Modify,
+            command: String::from(object.get_modify_command()),
+            object_id: Some(format!("{}", object.get_id())),
+            arguments: collect_arguments(fields, target)?,
+        })
+    }
+
+    /// Builds the create command for `object` from every field that does not hold its
+    /// default value. The id is sent if the identifier has a create switch, see
+    /// [`SystemObject::id_switch`]; a default id is left for the target system to assign.
+    pub fn create<ObjectType: CreatableObject + SystemObject>(
+        object: &ObjectType,
+        target: &TargetSystem,
+    ) -> Result<Self, CommandError> {
+        let fields = tracked_fields(object)
+            .into_iter()
+            .filter(|field| {
+                !field.is_default()
+                    && (!field.is_identifier()
+                        || field.switches(CommandType::Create).param.is_some())
+            })
+            .map(|field| (field, field.get_parameter(CommandType::Create)));
+
+        Ok(ModificationCommand {
+            command_type: CommandType::Create,
+            command: String::from(object.get_create_command()),
+            object_id: None,
+            arguments: collect_arguments(fields, target)?,
+        })
+    }
+
+    /// Builds the delete command for `object`, which only needs its id.
+    pub fn delete<ObjectType: DeletableObject>(object: &ObjectType) -> Self {
+        ModificationCommand {
+            command_type: CommandType::Delete,
+            command: String::from(object.get_delete_command()),
+            object_id: Some(format!("{}", object.get_id())),
+            arguments: Vec::new(),
+        }
+    }
+
+    pub fn command_type(&self) -> CommandType {
+        self.command_type
+    }
+
+    /// Whether this command sets the id of the object it creates, rather than leaving it
+    /// to the target system. `object` only provides the id switch, see
+    /// [`SystemObject::id_switch`].
+    pub fn sets_id<ObjectType: SystemObject>(&self, object: &ObjectType) -> bool {
+        object.id_switch(self.command_type).is_some_and(|switch| {
+            self.arguments
+                .iter()
+                .any(|argument| argument.name() == switch)
+        })
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn object_id(&self) -> Option<&str> {
+        self.object_id.as_deref()
+    }
+
+    pub fn arguments(&self) -> &[Parameter] {
+        &self.arguments
+    }
 }
 
+/// Two states of the same object, e.g. as read from the target system and as it should
+/// be. Unlike [`ModificationCommand::modify`], which relies on each field's own baseline,
+/// the differ compares the two instances member by member.
 pub struct Object<Type: Reflect + Clone> {
-    old: Type,
-    new: Type,
+    pub old: Type,
+    pub new: Type,
+}
+
+/// A member whose value differs between the two sides of an [`Object`]. Values are
+/// rendered the way they would appear in a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FieldChange {
+    pub path: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+struct ChangedField<'a> {
+    path: &'a str,
+    old: &'a dyn TrackedField,
+    old_reflect: &'a dyn Reflect,
+    new: &'a dyn TrackedField,
+}
+
+impl<Type: SystemObject + Clone> Object<Type> {
+    pub fn new(old: Type, new: Type) -> Self {
+        Object { old, new }
+    }
+
+    /// Pairs the baseline of `object`'s fields with their current values.
+    pub fn from_tracked(object: &Type) -> Self {
+        let mut old = object.clone();
+        old.rollback();
+        Object {
+            old,
+            new: object.clone(),
+        }
+    }
+
+    /// The members that differ between `old` and `new`, in declaration order. The
+    /// identifier is never reported.
+    pub fn changes(&self) -> Vec<FieldChange> {
+        self.changed_fields()
+            .into_iter()
+            .map(|field| FieldChange {
+                path: String::from(field.path),
+                old_value: field.old.formatted_value(),
+                new_value: field.new.formatted_value(),
+            })
+            .collect()
+    }
+
+    fn changed_fields(&self) -> Vec<ChangedField<'_>> {
+        let registry = field_registry::<Type>();
+
+        (0..self.new.field_len())
+            .filter_map(|index| {
+                let path = self.new.name_at(index)?;
+                let old_reflect = self.old.field_at(index)?;
+                let old = as_tracked(&registry, old_reflect)?;
+                let new = as_tracked(&registry, self.new.field_at(index)?)?;
+                Some(ChangedField {
+                    path,
+                    old,
+                    old_reflect,
+                    new,
+                })
+            })
+            .filter(|field| !field.new.is_identifier() && field.new.differs_from(field.old_reflect))
+            .collect()
+    }
+
+    /// How `target` treats each changed member that has a version filter.
+    pub fn version_decisions(&self, target: &TargetSystem) -> Vec<VersionDecision> {
+        self.changed_fields()
+            .into_iter()
+            .filter_map(|field| target.version_decision(field.new))
+            .collect()
+    }
+}
+
+impl<Type: SystemObject + ModifiableObject + Clone> Object<Type> {
+    /// Builds the modify command that turns `old` into `new`, targeting `new`'s id.
+    pub fn modify_command(
+        &self,
+        target: &TargetSystem,
+    ) -> Result<ModificationCommand, CommandError> {
+        let fields = self.changed_fields().into_iter().map(|field| {
+            let parameter = field
+                .new
+                .get_parameter_from(field.old_reflect, CommandType::Modify);
+            (field.new, parameter)
+        });
+
+        Ok(ModificationCommand {
+            command_type: CommandType::Modify,
+            command: String::from(self.new.get_modify_command()),
+            object_id: Some(format!("{}", self.new.get_id())),
+            arguments: collect_arguments(fields, target)?,
+        })
+    }
 }
 
-// to do this automatically need to be able to downcast into some concrete type that can perform the check
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn software_version_orders_by_major_then_minor() {
+        assert!(SoftwareVersion::new(4, 3) > SoftwareVersion::new(4, 2));
+        assert!(SoftwareVersion::new(4, 12) > SoftwareVersion::new(3, 188));
+        assert!(SoftwareVersion::new(3, 188) < SoftwareVersion::new(4, 0));
+        assert!(SoftwareVersion::new(4, 12) >= SoftwareVersion::new(4, 12));
+    }
+
+    #[test]
+    fn software_version_parses_common_forms() {
+        assert_eq!("4.12".parse(), Ok(SoftwareVersion::new(4, 12)));
+        assert_eq!(
+            "8.5.0.3".parse(),
+            Ok(SoftwareVersion::new(8, 5).with_patch(0).with_build(3))
+        );
+        assert_eq!(
+            "v7.8.1".parse(),
+            Ok(SoftwareVersion::new(7, 8).with_patch(1))
+        );
+        assert_eq!("9".parse(), Ok(SoftwareVersion::new(9, 0)));
+    }
+
+    #[test]
+    fn software_version_rejects_malformed_input() {
+        assert_eq!("".parse::<SoftwareVersion>(), Err(ParseVersionError::Empty));
+        assert_eq!(
+            "v".parse::<SoftwareVersion>(),
+            Err(ParseVersionError::Empty)
+        );
+        assert_eq!(
+            "4..12".parse::<SoftwareVersion>(),
+            Err(ParseVersionError::InvalidComponent)
+        );
+        assert_eq!(
+            "4.12-beta".parse::<SoftwareVersion>(),
+            Err(ParseVersionError::InvalidComponent)
+        );
+        assert_eq!(
+            "4.99999999999".parse::<SoftwareVersion>(),
+            Err(ParseVersionError::Overflow)
+        );
+        assert_eq!(
+            "8.5.0.3.1".parse::<SoftwareVersion>(),
+            Err(ParseVersionError::TooManyComponents)
+        );
+    }
+
+    #[test]
+    fn software_version_treats_missing_components_as_zero() {
+        assert_eq!("8.5".parse(), "8.5.0.0".parse::<SoftwareVersion>());
+        assert!(SoftwareVersion::new(8, 5).with_build(3) > SoftwareVersion::new(8, 5));
+        assert!(
+            SoftwareVersion::new(8, 5).with_patch(1) > SoftwareVersion::new(8, 5).with_build(9)
+        );
+        assert_eq!(SoftwareVersion::new(8, 5).to_string(), "8.5");
+        assert_eq!(
+            SoftwareVersion::new(8, 5).with_build(3).to_string(),
+            "8.5.0.3"
+        );
+    }
+
+    #[test]
+    fn version_filter_gates_on_fix_pack_level() {
+        let filter = VersionFilter::min_version(SoftwareVersion::parse("8.5.0.3"));
+
+        assert!(!filter.matches(SoftwareVersion::parse("8.5")));
+        assert!(!filter.matches(SoftwareVersion::parse("8.5.0.2")));
+        assert!(filter.matches(SoftwareVersion::parse("8.5.0.3")));
+        assert!(filter.matches(SoftwareVersion::parse("8.6")));
+    }
+
+    #[test]
+    fn software_version_parses_in_const_context() {
+        const ROLE_ID_SINCE: SoftwareVersion = SoftwareVersion::parse("4.12");
+
+        assert_eq!(ROLE_ID_SINCE, SoftwareVersion::new(4, 12));
+    }
+
+    fn software_version() -> impl Strategy<Value = SoftwareVersion> {
+        (0..20u32, 0..200u32, 0..3u32, 0..5u32).prop_map(|(major, minor, patch, build)| {
+            SoftwareVersion::new(major, minor)
+                .with_patch(patch)
+                .with_build(build)
+        })
+    }
+
+    fn components(version: SoftwareVersion) -> (u32, u32, u32, u32) {
+        (
+            version.major(),
+            version.minor(),
+            version.patch(),
+            version.build(),
+        )
+    }
+
+    fn version(version: &str) -> SoftwareVersion {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn version_filter_combinators() {
+        let readded =
+            VersionFilter::below(version("5.0")).or(VersionFilter::min_version(version("6.2")));
+        let not_broken = VersionFilter::min_version(version("4.12"))
+            .and(VersionFilter::excluding(version("4.15")));
+
+        assert!(readded.matches(version("4.12")));
+        assert!(!readded.matches(version("5.3")));
+        assert!(readded.matches(version("6.2")));
+        assert!(not_broken.matches(version("4.14")));
+        assert!(!not_broken.matches(version("4.15")));
+        assert!((!not_broken).matches(version("4.15")));
+    }
+
+    #[test]
+    fn version_filter_parses_expressions() {
+        let filter: VersionFilter = ">=4.12, <5.0 || =3.188".parse().unwrap();
+
+        assert_eq!(
+            filter,
+            VersionFilter::min_version(version("4.12"))
+                .and(VersionFilter::below(version("5.0")))
+                .or(VersionFilter::exactly(version("3.188")))
+        );
+        assert!(filter.matches(version("3.188")));
+        assert!(filter.matches(version("4.20")));
+        assert!(!filter.matches(version("3.189")));
+        assert!(!filter.matches(version("5.0")));
+    }
+
+    #[test]
+    fn version_filter_parses_negation_and_grouping() {
+        let filter: VersionFilter = ">=8.5, !(>=8.5.0.1, <8.5.0.3), != v8.6".parse().unwrap();
 
-// fn filter_changed_fields(field: &&dyn Reflect) -> bool {
-//     if let Some(field) = field.downcast_ref::<Field<dyn SystemObject, _>>() {
-//         match field {
-//             Field::Field(inner) => inner.old_value.is_some(),
-//             Field::VersionedField(inner, _) => inner.old_value.is_some()
-//         }
-//     } else {
-//         panic!("Couldn't downcast to field type")
-//     }
-// }
+        assert!(filter.matches(version("8.5")));
+        assert!(!filter.matches(version("8.5.0.2")));
+        assert!(filter.matches(version("8.5.0.3")));
+        assert!(!filter.matches(version("8.6")));
+    }
+
+    #[test]
+    fn version_filter_rejects_malformed_expressions() {
+        assert_eq!(
+            ">=4.12,".parse::<VersionFilter>(),
+            Err(ParseFilterError::UnexpectedEnd)
+        );
+        assert_eq!(
+            "4.12".parse::<VersionFilter>(),
+            Err(ParseFilterError::UnexpectedToken(String::from("4.12")))
+        );
+        assert_eq!(
+            ">=4.x".parse::<VersionFilter>(),
+            Err(ParseFilterError::Version(
+                String::from("4.x"),
+                ParseVersionError::InvalidComponent
+            ))
+        );
+        assert_eq!(
+            "(>=4.12".parse::<VersionFilter>(),
+            Err(ParseFilterError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn version_filter_display_uses_expression_syntax() {
+        let filter = VersionFilter::version_range(version("4.12"), version("5.0"))
+            .or(!VersionFilter::min_version(version("3.0")));
 
+        assert_eq!(filter.to_string(), ">=4.12, <=5.0 || !(>=3.0)");
+    }
+
+    fn version_filter() -> impl Strategy<Value = VersionFilter> {
+        let leaf = software_version().prop_flat_map(|version| {
+            prop_oneof![
+                Just(VersionFilter::min_version(version)),
+                Just(VersionFilter::max_version(version)),
+                Just(VersionFilter::above(version)),
+                Just(VersionFilter::below(version)),
+                Just(VersionFilter::exactly(version)),
+                Just(VersionFilter::excluding(version)),
+            ]
+        });
+        leaf.prop_recursive(3, 16, 3, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 2..4).prop_map(VersionFilter::All),
+                prop::collection::vec(inner.clone(), 2..4).prop_map(VersionFilter::Any),
+                inner.prop_map(|filter| !filter),
+            ]
+        })
+    }
 
-// struct IntoModificationCommant<ObjectType: ModifiableObject + Struct + SystemObject>(ObjectType);
-// impl<ObjectType: ModifiableObject + Struct + SystemObject> Into<ModificationCommand>
-//     for IntoModificationCommant<ObjectType>
-// {
-//     fn into(self) -> ModificationCommand {
-//         let inner = self.0;
+    proptest! {
+        #[test]
+        fn version_filter_display_round_trips(filter in version_filter(), version in software_version()) {
+            let parsed: VersionFilter = filter.to_string().parse().unwrap();
+            prop_assert_eq!(parsed.matches(version), filter.matches(version));
+        }
 
-//         let object_id = format!("{}", inner.get_id());
-//         let command_string = String::from(inner.get_modify_command());
+        #[test]
+        fn software_version_order_matches_components(a in software_version(), b in software_version()) {
+            prop_assert_eq!(a.cmp(&b), components(a).cmp(&components(b)));
+            prop_assert_eq!(a.cmp(&b), b.cmp(&a).reverse());
+            prop_assert_eq!(a == b, a.cmp(&b) == Ordering::Equal);
+        }
 
-//         let command_arguments: Vec<&dyn Reflect> = inner.iter_fields().filter(filter_changed_fields).collect();
+        #[test]
+        fn software_version_order_is_transitive(
+            a in software_version(),
+            b in software_version(),
+            c in software_version(),
+        ) {
+            if a <= b && b <= c {
+                prop_assert!(a <= c);
+            }
+        }
 
-//         ModificationCommand {
-//         }
-//     }
-// }
+        #[test]
+        fn software_version_display_round_trips(version in software_version()) {
+            prop_assert_eq!(version.to_string().parse(), Ok(version));
+        }
+    }
+}
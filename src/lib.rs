@@ -1,86 +1,85 @@
 #![feature(trait_alias)]
-use bevy_reflect::{Reflect, Struct};
-use internal::{
-    CommandType, Field, FieldInner, FieldParameter, IdentifiableObject, IsEmpty, ModifiableObject,
-    ModificationCommand, Parameter, SoftwareVersion, VersionFilter,
-};
-use log::warn;
-use std::{
-    any::Any,
-    cmp::Ordering,
-    marker::PhantomData,
-    ops::{Deref, DerefMut}, default,
-};
+extern crate self as fancy_stuff_with_reflection;
 
+pub use fancy_stuff_with_reflection_derive::system_object;
+
+pub mod executor;
 pub mod internal;
+pub mod parse;
+pub mod plan;
+pub mod reference;
+pub mod reflection;
+pub mod render;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod simulator;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod transaction;
+pub mod undo;
 
-const SYSTEM_VERSION: SoftwareVersion = SoftwareVersion::new(3, 188);
+#[cfg(feature = "serde")]
+pub use serde;
 
-#[derive(Default, Clone, Reflect)]
-enum UserFields {
-    #[default]
-    Id,
-    Name,
-    RoleId,
-}
+use reference::Ref;
 
-impl FieldParameter for UserFields {
-    fn get_parameter<FieldType: Default + IsEmpty + Reflect + Clone>(
-        &self,
-        command_type: CommandType,
-        old_value: &FieldInner<FieldType, UserFields>,
-        new_value: &FieldInner<FieldType, UserFields>,
-    ) -> Parameter {
-        match self {
-            Self::Id => unreachable!(),
-            Self::Name => {
-                if new_value.is_empty() {
-                    Parameter::Flag("-reset_name")
-                } else {
-                    Parameter::Parameter("-set_name")
-                }
-            }
-            Self::RoleId => Parameter::Parameter("-set_roleid"),
-        }
-    }
+/// Without the `serde` feature, system objects don't implement serde's traits.
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! impl_object_serde {
+    ($($tokens:tt)*) => {};
 }
 
-#[derive(Reflect)]
-pub struct User {
-    id: Field<u32, UserFields>,
-    name: Field<String, UserFields>,
-    role_id: Field<u32, UserFields>,
+/// Without the `serde` feature, fields have no serde type data to register.
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! register_field_serde {
+    ($($tokens:tt)*) => {};
 }
 
-impl IdentifiableObject for User {
-    fn get_id(&self) -> u32 {
-        *self.id
-    }
+/// A registry with every object type defined in this crate, see [`reflection`].
+pub fn type_registry() -> bevy_reflect::TypeRegistry {
+    let mut registry = bevy_reflect::TypeRegistry::default();
+    reflection::register_common(&mut registry);
+    reflection::register_object::<User>(&mut registry);
+    reflection::register_object::<Role>(&mut registry);
+    registry
 }
 
-impl ModifiableObject for User {
-    fn get_modify_command(&self) -> &'static str {
-        "update_user"
-    }
+#[system_object(modify = "update_user", create = "make_user", delete = "remove_user")]
+pub struct User {
+    #[field(id, create_param = "-id")]
+    id: u32,
+    #[field(
+        param = "-set_name",
+        reset_flag = "-reset_name",
+        create_param = "-name"
+    )]
+    name: String,
+    #[field(param = "-set_roleid", create_param = "-roleid", min_version = "4.12")]
+    role_id: Ref<Role>,
 }
 
-impl Default for User {
-    fn default() -> Self {
-        User {
-            id: Field::new("id", UserFields::Id),
-            name: Field::new("name", UserFields::Name),
-            role_id: Field::new_versioned(
-                "role_id",
-                UserFields::RoleId,
-                VersionFilter::min_version(SoftwareVersion::new(4, 12)),
-            ),
-        }
-    }
+#[system_object(modify = "update_role", create = "make_role", delete = "remove_role")]
+pub struct Role {
+    #[field(id, create_param = "-id")]
+    id: u32,
+    #[field(param = "-set_name", create_param = "-name")]
+    name: String,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bevy_reflect::Reflect;
+    use internal::{
+        CommandError, CommandType, Field, FieldChange, FieldDataType, FieldInner, FieldParameter,
+        FieldSwitches, ModificationCommand, Object, Parameter, ParameterError, SoftwareVersion,
+        SystemObject, TargetSystem, TrackedField, VersionFilter, VersionPolicy,
+    };
+    use test_support::{stored_user, target, user};
 
     #[test]
     fn it_works() {
@@ -90,4 +89,474 @@ mod tests {
 
         assert_eq!(*user.id, 45);
     }
+
+    #[test]
+    fn modify_command_contains_changed_fields() {
+        let mut user = User::default();
+        *user.id = 45;
+        *user.name = String::from("bob");
+
+        let command = ModificationCommand::modify(&user, &target()).unwrap();
+
+        assert_eq!(command.command(), "update_user");
+        assert_eq!(command.object_id(), Some("45"));
+        assert_eq!(
+            command.arguments(),
+            &[Parameter::Parameter("-set_name", String::from("bob"))]
+        );
+    }
+
+    #[test]
+    fn modify_command_skips_untouched_fields() {
+        let user = User::default();
+
+        let command = ModificationCommand::modify(&user, &target()).unwrap();
+
+        assert!(command.arguments().is_empty());
+    }
+
+    #[test]
+    fn emptied_fields_use_reset_flag() {
+        let mut user = User::default();
+        user.name.clear();
+        *user.role_id = Ref::new(3);
+
+        assert_eq!(
+            user.name.get_parameter(CommandType::Modify),
+            Parameter::Flag("-reset_name")
+        );
+        assert_eq!(
+            user.role_id.get_parameter(CommandType::Modify),
+            Parameter::Parameter("-set_roleid", String::from("3"))
+        );
+    }
+
+    #[test]
+    fn create_command_contains_non_default_fields() {
+        let mut user = User::default();
+        *user.name = String::from("bob");
+
+        let command = ModificationCommand::create(&user, &target()).unwrap();
+
+        assert_eq!(command.command_type(), CommandType::Create);
+        assert_eq!(command.command(), "make_user");
+        assert_eq!(command.object_id(), None);
+        assert_eq!(
+            command.arguments(),
+            &[Parameter::Parameter("-name", String::from("bob"))]
+        );
+    }
+
+    #[test]
+    fn create_command_sends_an_explicit_id() {
+        let command = ModificationCommand::create(&user(45, "bob", 2), &target()).unwrap();
+
+        assert_eq!(
+            command.arguments(),
+            &[
+                Parameter::Parameter("-id", String::from("45")),
+                Parameter::Parameter("-name", String::from("bob")),
+                Parameter::Parameter("-roleid", String::from("2"))
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_command_targets_id() {
+        let mut user = User::default();
+        *user.id = 45;
+        *user.name = String::from("bob");
+
+        let command = ModificationCommand::delete(&user);
+
+        assert_eq!(command.command_type(), CommandType::Delete);
+        assert_eq!(command.command(), "remove_user");
+        assert_eq!(command.object_id(), Some("45"));
+        assert!(command.arguments().is_empty());
+    }
+
+    #[test]
+    fn system_object_names_fields_after_members() {
+        let user = User::default();
+
+        assert_eq!(user.role_id.field_name(), "role_id");
+        assert!(user.id.is_identifier());
+        assert!(!user.name.is_identifier());
+    }
+
+    #[test]
+    fn system_object_maps_switches_back_to_fields() {
+        let mut user = User::default();
+
+        assert_eq!(
+            user.name.switches(CommandType::Modify),
+            FieldSwitches {
+                param: Some("-set_name"),
+                reset_flag: Some("-reset_name"),
+            }
+        );
+        assert_eq!(
+            user.field_for_switch(CommandType::Create, "-roleid")
+                .map(|field| field.field_name()),
+            Some("role_id")
+        );
+
+        user.apply_parameter(
+            CommandType::Modify,
+            &Parameter::Parameter("-set_roleid", String::from("3")),
+        )
+        .unwrap();
+        assert_eq!(*user.role_id, Ref::new(3));
+        assert_eq!(
+            user.apply_parameter(CommandType::Modify, &Parameter::Flag("-set_name")),
+            Err(ParameterError::MissingValue(String::from("-set_name")))
+        );
+    }
+
+    #[test]
+    fn setting_a_field_back_is_not_a_change() {
+        let mut user = User::default();
+        *user.name = String::from("bob");
+        *user.name = String::new();
+        user.role_id.set(Ref::new(3));
+        user.role_id.set(Ref::none());
+
+        let command = ModificationCommand::modify(&user, &target()).unwrap();
+
+        assert!(!user.name.is_changed());
+        assert!(command.arguments().is_empty());
+    }
+
+    #[test]
+    fn borrowing_a_field_mutably_is_not_a_change() {
+        let mut user = User::default();
+        user.name.push_str("");
+        *user.role_id = Ref::none();
+
+        assert!(!user.name.is_changed());
+        assert!(!user.role_id.is_changed());
+    }
+
+    #[test]
+    fn set_records_changes_without_deref_mut() {
+        let mut user = User::default();
+        user.name.set(String::from("bob"));
+
+        assert_eq!(user.name.get(), "bob");
+        assert!(user.name.is_changed());
+        assert_eq!(
+            ModificationCommand::modify(&user, &target())
+                .unwrap()
+                .arguments(),
+            &[Parameter::Parameter("-set_name", String::from("bob"))]
+        );
+    }
+
+    #[test]
+    fn modify_command_resets_emptied_fields() {
+        let mut user = User::default();
+        user.name.set(String::from("bob"));
+        user.commit();
+        user.name.clear();
+
+        let command = ModificationCommand::modify(&user, &target()).unwrap();
+
+        assert_eq!(command.arguments(), &[Parameter::Flag("-reset_name")]);
+    }
+
+    #[test]
+    fn commit_starts_a_new_edit_cycle() {
+        let mut user = User::default();
+        *user.id = 45;
+        *user.name = String::from("bob");
+        user.commit();
+
+        assert!(!user.is_changed());
+        assert!(ModificationCommand::modify(&user, &target())
+            .unwrap()
+            .arguments()
+            .is_empty());
+
+        *user.role_id = Ref::new(3);
+
+        assert_eq!(
+            ModificationCommand::modify(&user, &target())
+                .unwrap()
+                .arguments(),
+            &[Parameter::Parameter("-set_roleid", String::from("3"))]
+        );
+    }
+
+    #[test]
+    fn rollback_restores_baseline() {
+        let mut user = User::default();
+        *user.name = String::from("bob");
+        user.commit();
+        *user.name = String::from("alice");
+        *user.role_id = Ref::new(3);
+
+        user.rollback();
+
+        assert_eq!(user.name.get(), "bob");
+        assert_eq!(*user.role_id, Ref::none());
+        assert!(!user.is_changed());
+    }
+
+    #[test]
+    fn field_commit_and_rollback() {
+        let mut name: Field<String, UserFields> = Field::new("name", UserFields::Name);
+        name.set(String::from("bob"));
+        name.commit();
+        name.set(String::from("alice"));
+
+        assert!(name.is_changed());
+        name.rollback();
+        assert_eq!(name.get(), "bob");
+        assert!(!name.is_changed());
+    }
+
+    #[test]
+    fn same_object_targets_several_versions() {
+        let user = user(45, "bob", 3);
+        let old_system = TargetSystem::new(SoftwareVersion::new(3, 188))
+            .with_version_policy(VersionPolicy::Drop);
+        let new_system =
+            TargetSystem::new(SoftwareVersion::new(4, 20)).with_version_policy(VersionPolicy::Drop);
+
+        let old_command = ModificationCommand::modify(&user, &old_system).unwrap();
+        let new_command = ModificationCommand::modify(&user, &new_system).unwrap();
+
+        assert_eq!(
+            old_command.arguments(),
+            &[Parameter::Parameter("-set_name", String::from("bob"))]
+        );
+        assert_eq!(
+            new_command.arguments(),
+            &[
+                Parameter::Parameter("-set_name", String::from("bob")),
+                Parameter::Parameter("-set_roleid", String::from("3"))
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_fields_are_rejected_by_default() {
+        let user = user(45, "bob", 3);
+        let target = TargetSystem::new(SoftwareVersion::new(3, 188));
+
+        let error = ModificationCommand::modify(&user, &target).unwrap_err();
+
+        assert_eq!(
+            error,
+            CommandError::UnsupportedField {
+                field: String::from("role_id"),
+                version: SoftwareVersion::new(3, 188),
+                filter: VersionFilter::min_version(SoftwareVersion::new(4, 12)),
+            }
+        );
+    }
+
+    #[test]
+    fn warn_policy_keeps_unsupported_fields() {
+        let user = user(45, "bob", 3);
+        let target = TargetSystem::new(SoftwareVersion::new(3, 188))
+            .with_version_policy(VersionPolicy::Warn);
+
+        let command = ModificationCommand::create(&user, &target).unwrap();
+
+        assert_eq!(
+            command.arguments(),
+            &[
+                Parameter::Parameter("-id", String::from("45")),
+                Parameter::Parameter("-name", String::from("bob")),
+                Parameter::Parameter("-roleid", String::from("3"))
+            ]
+        );
+    }
+
+    #[system_object(modify = "update_group")]
+    struct Group {
+        #[field(id)]
+        id: u32,
+        #[field(param = "-set_remote", versions = "<5.0 || >=6.2")]
+        remote: bool,
+    }
+
+    #[test]
+    fn system_object_accepts_version_filter_expressions() {
+        let mut group = Group::default();
+        *group.remote = true;
+        let removed = TargetSystem::new(SoftwareVersion::new(5, 3));
+        let readded = TargetSystem::new(SoftwareVersion::new(6, 2));
+
+        assert!(ModificationCommand::modify(&group, &removed).is_err());
+        assert_eq!(
+            ModificationCommand::modify(&group, &readded)
+                .unwrap()
+                .arguments(),
+            &[Parameter::Parameter("-set_remote", String::from("true"))]
+        );
+    }
+
+    #[system_object(modify = "update_policy")]
+    struct Policy {
+        #[field(id)]
+        id: u32,
+        #[field(param = "-set_mode", versions = "!(=4.12.1 || <4.0), >=3.188")]
+        mode: u32,
+    }
+
+    #[test]
+    fn system_object_builds_the_filter_the_expression_parses_to() {
+        let expected = "!(=4.12.1 || <4.0), >=3.188"
+            .parse::<VersionFilter>()
+            .unwrap();
+
+        assert_eq!(Policy::default().mode.version_filter(), Some(&expected));
+        assert_eq!(
+            Group::default().remote.version_filter(),
+            Some(&"<5.0 || >=6.2".parse().unwrap())
+        );
+    }
+
+    #[derive(Default, Clone, Reflect)]
+    enum AccessFields {
+        #[default]
+        Locked,
+    }
+
+    impl FieldParameter for AccessFields {
+        fn get_parameter<FieldType: FieldDataType>(
+            &self,
+            _command_type: CommandType,
+            _old_value: &FieldInner<FieldType, Self>,
+            new_value: &FieldInner<FieldType, Self>,
+        ) -> Parameter {
+            Parameter::Parameter("-locked", self.format_value(new_value))
+        }
+
+        fn format_value<FieldType: FieldDataType>(
+            &self,
+            field: &FieldInner<FieldType, Self>,
+        ) -> String {
+            match field.format_argument().as_str() {
+                "true" => String::from("yes"),
+                "false" => String::from("no"),
+                other => String::from(other),
+            }
+        }
+    }
+
+    #[test]
+    fn format_value_can_be_overridden_per_field() {
+        let mut locked: Field<bool, AccessFields> = Field::new("locked", AccessFields::Locked);
+        *locked = true;
+
+        assert_eq!(
+            TrackedField::get_parameter(&locked, CommandType::Modify),
+            Parameter::Parameter("-locked", String::from("yes"))
+        );
+    }
+
+    /// A value type without serde impls, which fields accept whether or not the `serde`
+    /// feature is enabled.
+    #[derive(Default, Clone, PartialEq, Reflect)]
+    #[reflect_value(PartialEq)]
+    struct Mode(u8);
+
+    impl internal::IsEmpty for Mode {
+        fn is_empty(&self) -> bool {
+            false
+        }
+    }
+
+    impl internal::FormatArgument for Mode {
+        fn format_argument(&self) -> String {
+            format!("mode{}", self.0)
+        }
+    }
+
+    impl internal::ParseArgument for Mode {
+        fn parse_argument(argument: &str) -> Result<Self, internal::ParseArgumentError> {
+            let error = || internal::ParseArgumentError {
+                argument: String::from(argument),
+                expected: "mode",
+            };
+            let number = argument.strip_prefix("mode").ok_or_else(error)?;
+            number.parse().map(Mode).map_err(|_| error())
+        }
+    }
+
+    impl internal::AsReference for Mode {}
+
+    #[test]
+    fn fields_accept_values_without_serde_impls() {
+        let mut mode: Field<Mode, AccessFields> = Field::new("mode", AccessFields::Locked);
+        mode.set(Mode::parse_argument("mode2").unwrap());
+
+        assert!(mode.is_changed());
+        assert_eq!(TrackedField::formatted_value(&mode), "mode2");
+    }
+
+    #[test]
+    fn object_reports_changed_fields_with_both_values() {
+        let old = stored_user();
+        let mut new = old.clone();
+        *new.role_id = Ref::new(3);
+
+        assert_eq!(
+            Object::new(old, new).changes(),
+            vec![FieldChange {
+                path: String::from("role_id"),
+                old_value: String::from("2"),
+                new_value: String::from("3"),
+            }]
+        );
+    }
+
+    #[test]
+    fn object_diff_ignores_field_baselines() {
+        let mut old = User::default();
+        *old.id = 45;
+        *old.name = String::from("bob");
+        let mut new = stored_user();
+        *new.name = String::from("alice");
+        new.commit();
+
+        let object = Object::new(old, new);
+
+        assert_eq!(object.changes().len(), 2);
+        assert_eq!(
+            object.modify_command(&target()).unwrap().arguments(),
+            &[
+                Parameter::Parameter("-set_name", String::from("alice")),
+                Parameter::Parameter("-set_roleid", String::from("2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn object_diff_resets_emptied_fields() {
+        let old = stored_user();
+        let mut new = old.clone();
+        new.name.set(String::new());
+
+        let command = Object::new(old, new).modify_command(&target()).unwrap();
+
+        assert_eq!(command.object_id(), Some("45"));
+        assert_eq!(command.arguments(), &[Parameter::Flag("-reset_name")]);
+    }
+
+    #[test]
+    fn object_from_tracked_matches_field_baselines() {
+        let mut user = stored_user();
+        *user.name = String::from("alice");
+
+        let object = Object::from_tracked(&user);
+
+        assert_eq!(
+            object.modify_command(&target()).unwrap(),
+            ModificationCommand::modify(&user, &target()).unwrap()
+        );
+    }
 }
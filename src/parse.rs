@@ -0,0 +1,520 @@
+//! Reading target system text back into structured values: command lines into
+//! [`ModificationCommand`]s, using the objects' [`FieldParameter`] mappings in reverse,
+//! and listing output into objects, matching columns or keys to field names.
+//!
+//! [`FieldParameter`]: crate::internal::FieldParameter
+
+use std::{error::Error, fmt};
+
+use crate::internal::{
+    CommandType, ModificationCommand, Parameter, ParameterError, ParseArgumentError, SystemObject,
+};
+use crate::render::{QuoteStyle, SplitError};
+
+/// Why a command line could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseCommandError {
+    Split(SplitError),
+    Empty,
+    /// The command is not one of the object type's verbs.
+    UnknownCommand(String),
+    /// No field uses the switch in this type of command.
+    UnknownSwitch(String),
+    /// A value switch ends the line.
+    MissingValue(String),
+    /// A second positional argument follows the object id, or a create command has one
+    /// at all.
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseCommandError::Split(error) => write!(f, "{}", error),
+            ParseCommandError::Empty => write!(f, "empty command line"),
+            ParseCommandError::UnknownCommand(command) => write!(f, "unknown command {}", command),
+            ParseCommandError::UnknownSwitch(switch) => write!(f, "unknown switch {}", switch),
+            ParseCommandError::MissingValue(switch) => write!(f, "switch {} needs a value", switch),
+            ParseCommandError::UnexpectedArgument(argument) => {
+                write!(f, "unexpected argument {}", argument)
+            }
+        }
+    }
+}
+
+impl Error for ParseCommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseCommandError::Split(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SplitError> for ParseCommandError {
+    fn from(error: SplitError) -> Self {
+        ParseCommandError::Split(error)
+    }
+}
+
+impl ModificationCommand {
+    /// Parses a command line for `ObjectType`, the inverse of
+    /// [`ModificationCommand::to_command_line`]. The command type is found from the verb,
+    /// and each switch is matched to the field that uses it, so values are kept as
+    /// written and only checked when the command is applied.
+    pub fn parse<ObjectType: SystemObject + Default>(
+        line: &str,
+        style: QuoteStyle,
+    ) -> Result<Self, ParseCommandError> {
+        let argv = style.split(line)?;
+        let (command, rest) = argv.split_first().ok_or(ParseCommandError::Empty)?;
+        let command_type = [
+            CommandType::Create,
+            CommandType::Modify,
+            CommandType::Delete,
+        ]
+        .into_iter()
+        .find(|&command_type| ObjectType::command_name(command_type) == Some(command))
+        .ok_or_else(|| ParseCommandError::UnknownCommand(command.clone()))?;
+
+        let template = ObjectType::default();
+        let mut arguments = Vec::new();
+        let mut object_id = None;
+        let mut rest = rest.iter();
+        while let Some(argument) = rest.next() {
+            if !argument.starts_with('-') {
+                // Create commands name their object through switches, never positionally.
+                if command_type == CommandType::Create || object_id.is_some() {
+                    return Err(ParseCommandError::UnexpectedArgument(argument.clone()));
+                }
+                object_id = Some(argument.clone());
+                continue;
+            }
+
+            let switches = template
+                .field_for_switch(command_type, argument)
+                .map(|field| field.switches(command_type))
+                .ok_or_else(|| ParseCommandError::UnknownSwitch(argument.clone()))?;
+            match (switches.param, switches.reset_flag) {
+                (Some(param), _) if param == argument => {
+                    let value = rest
+                        .next()
+                        .ok_or_else(|| ParseCommandError::MissingValue(argument.clone()))?;
+                    arguments.push(Parameter::Parameter(param, value.clone()));
+                }
+                (_, Some(reset_flag)) => arguments.push(Parameter::Flag(reset_flag)),
+                _ => unreachable!("field_for_switch only finds fields using the switch"),
+            }
+        }
+
+        Ok(ModificationCommand::new(
+            command_type,
+            command.as_str(),
+            object_id,
+            arguments,
+        ))
+    }
+
+    /// Applies every argument to `object` through its [`SystemObject::apply_parameter`],
+    /// leaving the changes uncommitted. The object id is not applied.
+    pub fn apply_to<ObjectType: SystemObject>(
+        &self,
+        object: &mut ObjectType,
+    ) -> Result<(), ParameterError> {
+        self.arguments()
+            .iter()
+            .try_for_each(|argument| object.apply_parameter(self.command_type(), argument))
+    }
+}
+
+/// Why listing output could not be parsed. Line numbers start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseListingError {
+    /// The output has no header row.
+    MissingHeader,
+    /// A row has a different number of values than the header has columns.
+    ColumnCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidValue {
+        line: usize,
+        column: String,
+        error: ParseArgumentError,
+    },
+}
+
+impl fmt::Display for ParseListingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseListingError::MissingHeader => write!(f, "listing has no header row"),
+            ParseListingError::ColumnCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {} has {} values, expected {}",
+                line, found, expected
+            ),
+            ParseListingError::InvalidValue {
+                line,
+                column,
+                error,
+            } => write!(f, "line {}, column {}: {}", line, column, error),
+        }
+    }
+}
+
+impl Error for ParseListingError {}
+
+/// Parses the output of a listing command run with a delimiter, such as
+/// `lsuser -delim :`: a header row of field names, then one row per object. Cells are
+/// trimmed of surrounding whitespace. Columns are matched to fields by
+/// [`field_name`](crate::internal::TrackedField::field_name); columns no field has, e.g.
+/// ones added by newer versions, are ignored, and fields without a column keep their
+/// default. Every object is committed, so later edits produce minimal commands.
+pub fn parse_listing<ObjectType: SystemObject + Default>(
+    output: &str,
+    delimiter: char,
+) -> Result<Vec<ObjectType>, ParseListingError> {
+    let mut lines = output
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines.next().ok_or(ParseListingError::MissingHeader)?;
+    let columns = header.split(delimiter).map(str::trim).collect::<Vec<_>>();
+
+    lines
+        .map(|(index, line)| {
+            let values = line.split(delimiter).map(str::trim).collect::<Vec<_>>();
+            if values.len() != columns.len() {
+                return Err(ParseListingError::ColumnCount {
+                    line: index + 1,
+                    expected: columns.len(),
+                    found: values.len(),
+                });
+            }
+
+            let mut object = ObjectType::default();
+            for (column, value) in columns.iter().zip(values) {
+                object.set_named_field(column, value).map_err(|error| {
+                    ParseListingError::InvalidValue {
+                        line: index + 1,
+                        column: String::from(*column),
+                        error,
+                    }
+                })?;
+            }
+            object.commit();
+            Ok(object)
+        })
+        .collect()
+}
+
+/// An object read from detail output, with the keys that no field has.
+#[derive(Debug, Clone)]
+pub struct Detail<ObjectType> {
+    pub object: ObjectType,
+    pub unmapped_keys: Vec<String>,
+}
+
+/// Why detail output could not be parsed. Line numbers start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDetailError {
+    pub line: usize,
+    pub key: String,
+    pub error: ParseArgumentError,
+}
+
+impl fmt::Display for ParseDetailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, key {}: {}", self.line, self.key, self.error)
+    }
+}
+
+impl Error for ParseDetailError {}
+
+/// Parses the detail view of a listing command, such as `lsuser 45`: one `key value` or
+/// `key:value` pair per line. Keys are matched to fields by
+/// [`field_name`](crate::internal::TrackedField::field_name) and values are converted to
+/// each field's type. The object is committed, as with [`parse_listing`].
+pub fn parse_detail<ObjectType: SystemObject + Default>(
+    output: &str,
+) -> Result<Detail<ObjectType>, ParseDetailError> {
+    let mut object = ObjectType::default();
+    let mut unmapped_keys = Vec::new();
+
+    for (index, line) in output.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .split_once(|c: char| c == ':' || c.is_whitespace())
+            .unwrap_or((line, ""));
+        let mapped =
+            object
+                .set_named_field(key, value.trim())
+                .map_err(|error| ParseDetailError {
+                    line: index + 1,
+                    key: String::from(key),
+                    error,
+                })?;
+        if !mapped {
+            unmapped_keys.push(String::from(key));
+        }
+    }
+
+    object.commit();
+    Ok(Detail {
+        object,
+        unmapped_keys,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{SoftwareVersion, TargetSystem, TrackedField};
+    use crate::reference::Ref;
+    use crate::test_support::stored_user;
+    use crate::User;
+
+    #[test]
+    fn command_lines_parse_into_typed_commands() {
+        let command = ModificationCommand::parse::<User>(
+            r#"update_user -set_name "bob smith" -set_roleid 3 45"#,
+            QuoteStyle::Posix,
+        )
+        .unwrap();
+
+        assert_eq!(
+            command,
+            ModificationCommand::new(
+                CommandType::Modify,
+                "update_user",
+                Some(String::from("45")),
+                vec![
+                    Parameter::Parameter("-set_name", String::from("bob smith")),
+                    Parameter::Parameter("-set_roleid", String::from("3")),
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn parsing_round_trips_generated_commands() {
+        let mut user = stored_user();
+        *user.name = String::from("bob o'neil");
+        let target = TargetSystem::new(SoftwareVersion::new(4, 12));
+        let commands = [
+            ModificationCommand::modify(&user, &target).unwrap(),
+            ModificationCommand::create(&user, &target).unwrap(),
+            ModificationCommand::delete(&user),
+        ];
+
+        for command in commands {
+            let line = command.to_command_line(QuoteStyle::Posix);
+            assert_eq!(
+                ModificationCommand::parse::<User>(&line, QuoteStyle::Posix).unwrap(),
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn parsed_commands_apply_to_objects() {
+        let mut user = stored_user();
+        let command = ModificationCommand::parse::<User>(
+            "update_user -reset_name -set_roleid 7 45",
+            QuoteStyle::Posix,
+        )
+        .unwrap();
+
+        command.apply_to(&mut user).unwrap();
+
+        assert_eq!(*user.name, "");
+        assert_eq!(*user.role_id, Ref::new(7));
+        assert!(user.is_changed());
+    }
+
+    #[test]
+    fn create_commands_build_new_objects() {
+        let command = ModificationCommand::parse::<User>(
+            "make_user -name carol -roleid 5",
+            QuoteStyle::Posix,
+        )
+        .unwrap();
+        let mut user = User::default();
+
+        command.apply_to(&mut user).unwrap();
+
+        assert_eq!(command.command_type(), CommandType::Create);
+        assert_eq!(*user.name, "carol");
+        assert_eq!(*user.role_id, Ref::new(5));
+    }
+
+    #[test]
+    fn invalid_values_are_reported_when_applied() {
+        let command = ModificationCommand::parse::<User>(
+            "update_user -set_roleid admin 45",
+            QuoteStyle::Posix,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            command.apply_to(&mut stored_user()),
+            Err(ParameterError::InvalidValue(..))
+        ));
+    }
+
+    #[test]
+    fn create_commands_take_no_positional_arguments() {
+        assert_eq!(
+            ModificationCommand::parse::<User>("make_user -name x 45", QuoteStyle::Posix),
+            Err(ParseCommandError::UnexpectedArgument(String::from("45")))
+        );
+        assert_eq!(
+            ModificationCommand::parse::<User>("make_user -id 45 -name x", QuoteStyle::Posix)
+                .unwrap()
+                .object_id(),
+            None
+        );
+    }
+
+    #[test]
+    fn malformed_command_lines_are_rejected() {
+        let parse = |line| ModificationCommand::parse::<User>(line, QuoteStyle::Posix);
+
+        assert_eq!(parse("  "), Err(ParseCommandError::Empty));
+        assert_eq!(
+            parse("chuser -set_name bob 45"),
+            Err(ParseCommandError::UnknownCommand(String::from("chuser")))
+        );
+        assert_eq!(
+            parse("update_user -name bob 45"),
+            Err(ParseCommandError::UnknownSwitch(String::from("-name")))
+        );
+        assert_eq!(
+            parse("update_user 45 -set_name"),
+            Err(ParseCommandError::MissingValue(String::from("-set_name")))
+        );
+        assert_eq!(
+            parse("update_user -set_name bob 45 46"),
+            Err(ParseCommandError::UnexpectedArgument(String::from("46")))
+        );
+        assert_eq!(
+            parse("update_user -set_name 'bob"),
+            Err(ParseCommandError::Split(SplitError::UnterminatedQuote))
+        );
+    }
+
+    const LSUSER: &str = "\
+id:name:role_id:usergrp_name
+45:bob smith:3:admins
+46:carol::
+";
+
+    #[test]
+    fn listings_parse_into_clean_objects() {
+        let users = parse_listing::<User>(LSUSER, ':').unwrap();
+
+        assert_eq!(users.len(), 2);
+        assert_eq!(*users[0].id, 45);
+        assert_eq!(*users[0].name, "bob smith");
+        assert_eq!(*users[0].role_id, Ref::new(3));
+        assert_eq!(*users[1].name, "carol");
+        assert_eq!(*users[1].role_id, Ref::none());
+        assert!(users.iter().all(|user| !user.is_changed()));
+    }
+
+    #[test]
+    fn listed_objects_produce_minimal_commands() {
+        let mut users = parse_listing::<User>(LSUSER, ':').unwrap();
+        *users[0].role_id = Ref::new(4);
+
+        let command =
+            ModificationCommand::modify(&users[0], &TargetSystem::new(SoftwareVersion::new(4, 12)))
+                .unwrap();
+
+        assert_eq!(
+            command.arguments(),
+            &[Parameter::Parameter("-set_roleid", String::from("4"))]
+        );
+    }
+
+    #[test]
+    fn padded_listings_are_trimmed() {
+        let users =
+            parse_listing::<User>("id : name : role_id\n45 : bob smith : 3\n", ':').unwrap();
+
+        assert_eq!(*users[0].id, 45);
+        assert_eq!(*users[0].name, "bob smith");
+        assert_eq!(*users[0].role_id, Ref::new(3));
+    }
+
+    #[test]
+    fn columns_missing_on_older_versions_keep_defaults() {
+        let users = parse_listing::<User>("id!name\n45!bob\n", '!').unwrap();
+
+        assert_eq!(*users[0].name, "bob");
+        assert_eq!(*users[0].role_id, Ref::none());
+        assert!(users[0].role_id.is_default());
+    }
+
+    #[test]
+    fn malformed_listings_are_rejected() {
+        assert_eq!(
+            parse_listing::<User>("\n", ':').err(),
+            Some(ParseListingError::MissingHeader)
+        );
+        assert_eq!(
+            parse_listing::<User>("id:name\n45:bob:extra\n", ':').err(),
+            Some(ParseListingError::ColumnCount {
+                line: 2,
+                expected: 2,
+                found: 3,
+            })
+        );
+        assert!(matches!(
+            parse_listing::<User>("id:role_id\n45:admin\n", ':').err(),
+            Some(ParseListingError::InvalidValue { line: 2, ref column, .. }) if column == "role_id"
+        ));
+    }
+
+    #[test]
+    fn detail_output_fills_one_object() {
+        let output = "id 45\nname bob smith\nrole_id 3\nusergrp_name admins\nlocked no\n";
+
+        let detail = parse_detail::<User>(output).unwrap();
+
+        assert_eq!(*detail.object.id, 45);
+        assert_eq!(*detail.object.name, "bob smith");
+        assert_eq!(*detail.object.role_id, Ref::new(3));
+        assert!(!detail.object.is_changed());
+        assert_eq!(detail.unmapped_keys, vec!["usergrp_name", "locked"]);
+    }
+
+    #[test]
+    fn detail_output_accepts_colon_separators_and_blank_values() {
+        let detail = parse_detail::<User>("id:45\nname:\nrole_id: 7\n").unwrap();
+
+        assert_eq!(*detail.object.id, 45);
+        assert_eq!(*detail.object.name, "");
+        assert_eq!(*detail.object.role_id, Ref::new(7));
+        assert!(detail.unmapped_keys.is_empty());
+    }
+
+    #[test]
+    fn detail_values_must_match_field_types() {
+        let error = parse_detail::<User>("id 45\nrole_id admin\n")
+            .err()
+            .unwrap();
+
+        assert_eq!(error.line, 2);
+        assert_eq!(error.key, "role_id");
+        assert_eq!(error.error.expected, "u32");
+    }
+}
@@ -0,0 +1,848 @@
+//! Desired-state reconciliation: comparing the objects that should exist with the ones
+//! that do, and turning the difference into commands.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    error::Error,
+    fmt,
+};
+
+use crate::executor::{run_checked, ApplyError, CommandExecutor, CommandOutput};
+use crate::internal::{
+    short_type_name, CommandError, CommandType, CreatableObject, DeletableObject, FieldChange,
+    IdentifiableObject, ModifiableObject, ModificationCommand, Object, SystemObject, TargetSystem,
+    VersionDecision,
+};
+use crate::reference::ObjectReference;
+use crate::render::QuoteStyle;
+use crate::undo::{back_out_script, ReversibleCommand};
+
+/// One command of a [`Plan`], with the changes it makes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanEntry {
+    object: ObjectReference,
+    command: ReversibleCommand,
+    changes: Vec<FieldChange>,
+    version_decisions: Vec<VersionDecision>,
+    /// The objects the desired state refers to, which must exist before the command runs.
+    requires: Vec<ObjectReference>,
+    /// The objects the actual state refers to, which can't be deleted before it runs.
+    releases: Vec<ObjectReference>,
+}
+
+impl PlanEntry {
+    /// The object's type name, e.g. `User`.
+    pub fn object_type(&self) -> &'static str {
+        self.object.object_type()
+    }
+
+    pub fn object_id(&self) -> u32 {
+        self.object.object_id()
+    }
+
+    /// The object this entry creates, modifies or deletes.
+    pub fn object(&self) -> ObjectReference {
+        self.object
+    }
+
+    pub fn command_type(&self) -> CommandType {
+        self.command.command().command_type()
+    }
+
+    pub fn command(&self) -> &ModificationCommand {
+        self.command.command()
+    }
+
+    /// The command that backs this entry out, see [`ReversibleCommand`].
+    pub fn inverse(&self) -> &ModificationCommand {
+        self.command.inverse()
+    }
+
+    pub fn reversible_command(&self) -> &ReversibleCommand {
+        &self.command
+    }
+
+    /// The fields the command changes. Creates list every field that differs from its
+    /// default; deletes list none.
+    pub fn changes(&self) -> &[FieldChange] {
+        &self.changes
+    }
+
+    /// How the target treated each changed field that has a version filter.
+    pub fn version_decisions(&self) -> &[VersionDecision] {
+        &self.version_decisions
+    }
+}
+
+/// The commands that turn the actual state of a target system into the desired one.
+/// Objects of several types can be reconciled into the same plan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    entries: Vec<PlanEntry>,
+    skipped: Vec<PlanEntry>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Plan::default()
+    }
+
+    /// Adds the commands for one type of object, matching `desired` and `actual` objects
+    /// by [`get_id`](crate::internal::IdentifiableObject::get_id): desired objects that
+    /// don't exist are created, existing ones that differ are modified, and actual objects
+    /// that aren't desired are deleted. Creates send the desired id, so that the next
+    /// reconcile finds the object; this needs a create switch on the type's identifier,
+    /// see [`SystemObject::id_switch`], and an id other than the default. Entries are
+    /// ordered by id, deletes last, and then by the [`Ref`](crate::reference::Ref)s
+    /// between objects of every type reconciled so far: a referenced object is created
+    /// before the objects that refer to it, and deleted after them. If the references
+    /// form a cycle, or two desired or two actual objects have the same id, the plan is
+    /// left unchanged.
+    ///
+    /// A modify whose every change the target's [`VersionPolicy`] drops has no command to
+    /// run, so it goes to [`Plan::skipped`] instead of the entries.
+    ///
+    /// [`VersionPolicy`]: crate::internal::VersionPolicy
+    pub fn reconcile<ObjectType>(
+        &mut self,
+        desired: &[ObjectType],
+        actual: &[ObjectType],
+        target: &TargetSystem,
+    ) -> Result<(), ReconcileError>
+    where
+        ObjectType:
+            SystemObject + ModifiableObject + CreatableObject + DeletableObject + Default + Clone,
+    {
+        let desired = by_id(desired)?;
+        let actual = by_id(actual)?;
+        let entry = |object: &ObjectType, command, diff: Option<Object<ObjectType>>| PlanEntry {
+            object: ObjectReference::new::<ObjectType>(object.get_id()),
+            command,
+            changes: diff.as_ref().map(Object::changes).unwrap_or_default(),
+            version_decisions: diff
+                .as_ref()
+                .map(|diff| diff.version_decisions(target))
+                .unwrap_or_default(),
+            requires: diff
+                .as_ref()
+                .map(|diff| diff.new.references())
+                .unwrap_or_default(),
+            releases: match diff {
+                Some(diff) => diff.old.references(),
+                None => object.references(),
+            },
+        };
+        let mut entries = self.entries.clone();
+        let mut skipped = Vec::new();
+
+        for (id, object) in &desired {
+            match actual.get(id) {
+                None => {
+                    let command = ReversibleCommand::create(*object, target)?;
+                    // A create that leaves the id to the target would never match `object`.
+                    if command.inverse().object_id().is_none() {
+                        return Err(CommandError::IdNotSettable {
+                            object_type: short_type_name::<ObjectType>(),
+                            object_id: *id,
+                        }
+                        .into());
+                    }
+                    let diff = Object::new(ObjectType::default(), (*object).clone());
+                    entries.push(entry(object, command, Some(diff)));
+                }
+                Some(existing) => {
+                    let diff = Object::new((*existing).clone(), (*object).clone());
+                    if !diff.changes().is_empty() {
+                        let inverse = Object::new(diff.new.clone(), diff.old.clone());
+                        let command = ReversibleCommand::new(
+                            diff.modify_command(target)?,
+                            inverse.modify_command(target)?,
+                        );
+                        if command.command().arguments().is_empty() {
+                            skipped.push(entry(object, command, Some(diff)));
+                        } else {
+                            entries.push(entry(object, command, Some(diff)));
+                        }
+                    }
+                }
+            }
+        }
+        for (id, object) in &actual {
+            if !desired.contains_key(id) {
+                let command = ReversibleCommand::delete(*object, target)?;
+                entries.push(entry(object, command, None));
+            }
+        }
+        self.entries = order_by_dependencies(entries)?;
+        self.skipped.append(&mut skipped);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[PlanEntry] {
+        &self.entries
+    }
+
+    /// Modifies left out of the plan because the target dropped every change, kept so
+    /// that their [`version_decisions`](PlanEntry::version_decisions) can be reviewed.
+    /// Their commands have no arguments and are never run.
+    pub fn skipped(&self) -> &[PlanEntry] {
+        &self.skipped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The commands that back the whole plan out, last entry first.
+    pub fn back_out_script(&self, style: QuoteStyle) -> String {
+        back_out_script(
+            self.entries.iter().map(PlanEntry::reversible_command),
+            style,
+        )
+    }
+
+    /// Runs every command in order, stopping at the first one that fails. Returns the
+    /// output of each command.
+    pub fn apply(
+        &self,
+        executor: &mut impl CommandExecutor,
+    ) -> Result<Vec<CommandOutput>, PlanError> {
+        let mut outputs = Vec::with_capacity(self.entries.len());
+        for (index, entry) in self.entries.iter().enumerate() {
+            let output = run_checked(executor, entry.command())
+                .and_then(|output| entry.reversible_command().applied(&output).map(|_| output))
+                .map_err(|error| PlanError {
+                    entry: index,
+                    error,
+                })?;
+            outputs.push(output);
+        }
+        Ok(outputs)
+    }
+}
+
+/// Sorts `entries` so that each runs after the entries that precede it. Of the entries
+/// free to run, deletes go after the others, and otherwise the earliest goes first, so
+/// entries that don't depend on each other keep their order.
+fn order_by_dependencies(entries: Vec<PlanEntry>) -> Result<Vec<PlanEntry>, DependencyCycle> {
+    let mut by_object = HashMap::<ObjectReference, Vec<usize>>::new();
+    for (index, entry) in entries.iter().enumerate() {
+        by_object.entry(entry.object).or_default().push(index);
+    }
+    let entries_for = |objects: &[ObjectReference], command_type| {
+        objects
+            .iter()
+            .filter_map(|object| by_object.get(object))
+            .flatten()
+            .copied()
+            .filter(|&index| entries[index].command_type() == command_type)
+            .collect::<Vec<_>>()
+    };
+
+    let mut successors = vec![Vec::new(); entries.len()];
+    let mut predecessors = vec![Vec::new(); entries.len()];
+    // Objects are created before the entries that refer to them, and deleted after the
+    // entries that stop referring to them.
+    for (index, entry) in entries.iter().enumerate() {
+        let edges = entries_for(&entry.requires, CommandType::Create)
+            .into_iter()
+            .map(|create| (create, index))
+            .chain(
+                entries_for(&entry.releases, CommandType::Delete)
+                    .into_iter()
+                    .map(|delete| (index, delete)),
+            );
+        for (before, after) in edges.filter(|(before, after)| before != after) {
+            successors[before].push(after);
+            predecessors[after].push(before);
+        }
+    }
+
+    let priority =
+        |index: usize| Reverse((entries[index].command_type() == CommandType::Delete, index));
+    let mut waiting = predecessors.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut ready = (0..entries.len())
+        .filter(|&index| waiting[index] == 0)
+        .map(priority)
+        .collect::<BinaryHeap<_>>();
+    let mut order = Vec::with_capacity(entries.len());
+    while let Some(Reverse((_, index))) = ready.pop() {
+        order.push(index);
+        for &next in &successors[index] {
+            waiting[next] -= 1;
+            if waiting[next] == 0 {
+                ready.push(priority(next));
+            }
+        }
+    }
+
+    if order.len() < entries.len() {
+        return Err(find_cycle(&entries, &predecessors, &waiting));
+    }
+    let mut entries = entries.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| entries[index].take())
+        .collect())
+}
+
+/// Finds a cycle among the entries still `waiting` once sorting is stuck. Each of them
+/// waits for another one that is still waiting, so walking back through those must
+/// come round to an entry it has already passed.
+fn find_cycle(
+    entries: &[PlanEntry],
+    predecessors: &[Vec<usize>],
+    waiting: &[usize],
+) -> DependencyCycle {
+    let start = (0..entries.len())
+        .find(|&index| waiting[index] > 0)
+        .expect("sorting stopped early, so an entry is still waiting");
+    let mut path = vec![start];
+    let mut visited = vec![None; entries.len()];
+    loop {
+        let current = path[path.len() - 1];
+        visited[current] = Some(path.len() - 1);
+        let previous = predecessors[current]
+            .iter()
+            .copied()
+            .find(|&previous| waiting[previous] > 0)
+            .expect("waiting entries wait for another waiting entry");
+        if let Some(position) = visited[previous] {
+            let cycle = path[position..]
+                .iter()
+                .rev()
+                .map(|&index| entries[index].object)
+                .collect();
+            return DependencyCycle { cycle };
+        }
+        path.push(previous);
+    }
+}
+
+fn by_id<ObjectType: SystemObject + IdentifiableObject>(
+    objects: &[ObjectType],
+) -> Result<BTreeMap<u32, &ObjectType>, ReconcileError> {
+    let mut by_id = BTreeMap::new();
+    for object in objects {
+        if by_id.insert(object.get_id(), object).is_some() {
+            return Err(ReconcileError::DuplicateId(ObjectReference::new::<
+                ObjectType,
+            >(object.get_id())));
+        }
+    }
+    Ok(by_id)
+}
+
+/// Lists the entries the way a reviewer reads them: `+` for creates, `~` for modifies
+/// and `-` for deletes, each with its command line and changed fields, then the skipped
+/// modifies marked with `=`.
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.entries.is_empty() && self.skipped.is_empty() {
+            return writeln!(f, "No changes.");
+        }
+        for entry in &self.entries {
+            let symbol = match entry.command_type() {
+                CommandType::Create => '+',
+                CommandType::Modify => '~',
+                CommandType::Delete => '-',
+            };
+            writeln!(
+                f,
+                "{} {} {}: {}",
+                symbol,
+                entry.object_type(),
+                entry.object_id(),
+                entry.command().to_command_line(QuoteStyle::Posix)
+            )?;
+            write_changes(f, entry)?;
+        }
+        for entry in &self.skipped {
+            writeln!(
+                f,
+                "= {} {}: skipped, the target supports none of the changes",
+                entry.object_type(),
+                entry.object_id()
+            )?;
+            write_changes(f, entry)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_changes(f: &mut fmt::Formatter<'_>, entry: &PlanEntry) -> fmt::Result {
+    for change in &entry.changes {
+        writeln!(
+            f,
+            "    {}: {:?} -> {:?}",
+            change.path, change.old_value, change.new_value
+        )?;
+    }
+    Ok(())
+}
+
+/// Why applying a [`Plan`] stopped. Entries before `entry` have been applied.
+#[derive(Debug)]
+pub struct PlanError {
+    pub entry: usize,
+    pub error: ApplyError,
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "plan entry {}: {}", self.entry, self.error)
+    }
+}
+
+impl Error for PlanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Objects whose commands each have to run before the next one's, and the last one's
+/// before the first's, so no order of them works.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyCycle {
+    pub cycle: Vec<ObjectReference>,
+}
+
+impl fmt::Display for DependencyCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dependency cycle: ")?;
+        for object in &self.cycle {
+            write!(f, "{} -> ", object)?;
+        }
+        match self.cycle.first() {
+            Some(first) => write!(f, "{}", first),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for DependencyCycle {}
+
+/// Why [`Plan::reconcile`] couldn't add an object's commands.
+#[derive(Debug)]
+pub enum ReconcileError {
+    Command(CommandError),
+    Cycle(DependencyCycle),
+    /// Two desired objects, or two actual ones, have the same id.
+    DuplicateId(ObjectReference),
+}
+
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReconcileError::Command(error) => write!(f, "{}", error),
+            ReconcileError::Cycle(cycle) => write!(f, "{}", cycle),
+            ReconcileError::DuplicateId(object) => {
+                write!(f, "{} is listed more than once", object)
+            }
+        }
+    }
+}
+
+impl Error for ReconcileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReconcileError::Command(error) => Some(error),
+            ReconcileError::Cycle(cycle) => Some(cycle),
+            ReconcileError::DuplicateId(_) => None,
+        }
+    }
+}
+
+impl From<CommandError> for ReconcileError {
+    fn from(error: CommandError) -> Self {
+        ReconcileError::Command(error)
+    }
+}
+
+impl From<DependencyCycle> for ReconcileError {
+    fn from(cycle: DependencyCycle) -> Self {
+        ReconcileError::Cycle(cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    use crate::executor::RecordingExecutor;
+    use crate::internal::{Parameter, SoftwareVersion, VersionOutcome, VersionPolicy};
+    use crate::parse::parse_listing;
+    use crate::reference::Ref;
+    use crate::simulator::SimulatedSystem;
+    use crate::test_support::{role, target, user};
+    use crate::{system_object, Role, User};
+
+    const LSUSER: &str = "id:name:role_id\n45:bob:2\n46:carol:5\n47:dave:1\n";
+    const LSROLE: &str = "id:name\n1:operators\n2:admins\n5:monitors\n";
+
+    fn desired() -> Vec<User> {
+        vec![
+            user(45, "bob smith", 2),
+            user(46, "carol", 5),
+            user(48, "erin", 3),
+        ]
+    }
+
+    fn plan() -> Plan {
+        let actual = parse_listing::<User>(LSUSER, ':').unwrap();
+        let mut plan = Plan::new();
+        plan.reconcile(&desired(), &actual, &target()).unwrap();
+        plan
+    }
+
+    #[test]
+    fn plans_create_modify_and_delete_by_id() {
+        let plan = plan();
+        let summary = plan
+            .entries()
+            .iter()
+            .map(|entry| (entry.command_type(), entry.object_id()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            vec![
+                (CommandType::Modify, 45),
+                (CommandType::Create, 48),
+                (CommandType::Delete, 47),
+            ]
+        );
+        assert_eq!(
+            plan.entries()[0].command().arguments(),
+            &[Parameter::Parameter("-set_name", String::from("bob smith"))]
+        );
+    }
+
+    #[test]
+    fn plans_print_for_review() {
+        assert_eq!(
+            plan().to_string(),
+            "\
+~ User 45: update_user -set_name 'bob smith' 45
+    name: \"bob\" -> \"bob smith\"
++ User 48: make_user -id 48 -name erin -roleid 3
+    name: \"\" -> \"erin\"
+    role_id: \"\" -> \"3\"
+- User 47: remove_user 47
+"
+        );
+        assert_eq!(Plan::new().to_string(), "No changes.\n");
+    }
+
+    #[test]
+    fn plans_back_out_last_entry_first() {
+        assert_eq!(
+            plan().back_out_script(QuoteStyle::Posix),
+            "\
+make_user -id 47 -name dave -roleid 1
+remove_user 48
+update_user -set_name bob 45
+"
+        );
+    }
+
+    #[test]
+    fn applied_plans_reach_the_desired_state() {
+        let mut device = SimulatedSystem::new(SoftwareVersion::new(4, 12));
+        for user in parse_listing::<User>(LSUSER, ':').unwrap() {
+            device.insert(user);
+        }
+
+        plan().apply(&mut device).unwrap();
+
+        let names = device
+            .objects()
+            .map(|user| user.name.get().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["bob smith", "carol", "erin"]);
+    }
+
+    #[test]
+    fn applied_plans_converge() {
+        let mut device = SimulatedSystem::new(SoftwareVersion::new(4, 12));
+        device.insert(user(45, "bob", 2));
+        let desired = vec![user(45, "bob", 2), user(60, "frank", 3)];
+        let mut first = Plan::new();
+        let actual = device.objects().cloned().collect::<Vec<_>>();
+        first.reconcile(&desired, &actual, &target()).unwrap();
+
+        first.apply(&mut device).unwrap();
+
+        let mut second = Plan::new();
+        let actual = device.objects().cloned().collect::<Vec<_>>();
+        second.reconcile(&desired, &actual, &target()).unwrap();
+        assert!(second.is_empty(), "{}", second);
+        assert_eq!(*device.get(60).unwrap().name, "frank");
+    }
+
+    #[test]
+    fn modifies_with_only_dropped_changes_are_skipped() {
+        let target = TargetSystem::new(SoftwareVersion::new(3, 188))
+            .with_version_policy(VersionPolicy::Drop);
+        let actual = parse_listing::<User>(LSUSER, ':').unwrap();
+        let mut desired = actual.clone();
+        *desired[1].role_id = Ref::new(9);
+        let mut plan = Plan::new();
+
+        plan.reconcile(&desired, &actual, &target).unwrap();
+
+        assert!(plan.is_empty());
+        assert_eq!(
+            plan.skipped()[0].version_decisions()[0].outcome,
+            VersionOutcome::Dropped
+        );
+        assert_eq!(
+            plan.to_string(),
+            "\
+= User 46: skipped, the target supports none of the changes
+    role_id: \"5\" -> \"9\"
+"
+        );
+        let mut executor = RecordingExecutor::new();
+        plan.apply(&mut executor).unwrap();
+        assert!(executor.commands().is_empty());
+    }
+
+    #[test]
+    fn failed_plans_report_the_failing_entry() {
+        let mut executor = RecordingExecutor::new()
+            .respond_with(CommandOutput::success(""))
+            .respond_with(CommandOutput::failure(1, "CMMVC5709E bad"));
+
+        let error = plan().apply(&mut executor).unwrap_err();
+
+        assert_eq!(error.entry, 1);
+        assert!(matches!(error.error, ApplyError::Failed(_)));
+        assert_eq!(executor.commands().len(), 2);
+    }
+
+    #[test]
+    fn referenced_objects_are_created_first_and_deleted_last() {
+        let mut plan = plan();
+        let desired = vec![role(2, "admins"), role(3, "auditors"), role(5, "monitors")];
+        let actual = parse_listing::<Role>(LSROLE, ':').unwrap();
+
+        plan.reconcile(&desired, &actual, &target()).unwrap();
+
+        let summary = plan
+            .entries()
+            .iter()
+            .map(|entry| (entry.command_type(), entry.object().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (CommandType::Modify, String::from("User 45")),
+                (CommandType::Create, String::from("Role 3")),
+                (CommandType::Create, String::from("User 48")),
+                (CommandType::Delete, String::from("User 47")),
+                (CommandType::Delete, String::from("Role 1")),
+            ]
+        );
+    }
+
+    /// Roles and users on one simulated device, which refuses commands that would leave
+    /// a user referring to a role that doesn't exist.
+    struct Device {
+        roles: SimulatedSystem<Role>,
+        users: SimulatedSystem<User>,
+    }
+
+    impl CommandExecutor for Device {
+        fn execute(&mut self, command: &ModificationCommand) -> io::Result<CommandOutput> {
+            let refused = CommandOutput::failure(1, "CMMVC5753E The object is in use.");
+            if command.command().ends_with("_role") {
+                let in_use = self.users.objects().any(|user| {
+                    user.role_id.id().map(|id| id.to_string()).as_deref() == command.object_id()
+                });
+                if command.command_type() == CommandType::Delete && in_use {
+                    return Ok(refused);
+                }
+                return self.roles.execute(command);
+            }
+
+            let role = command
+                .arguments()
+                .iter()
+                .find(|argument| matches!(argument.name(), "-roleid" | "-set_roleid"))
+                .and_then(Parameter::value);
+            if role.is_some_and(|role| role.parse().map_or(true, |id| self.roles.get(id).is_none()))
+            {
+                return Ok(refused);
+            }
+            self.users.execute(command)
+        }
+    }
+
+    #[test]
+    fn referenced_objects_exist_whenever_they_are_referred_to() {
+        let version = SoftwareVersion::new(4, 12);
+        let mut device = Device {
+            roles: SimulatedSystem::new(version),
+            users: SimulatedSystem::new(version),
+        };
+        parse_listing::<Role>(LSROLE, ':')
+            .unwrap()
+            .into_iter()
+            .for_each(|role| device.roles.insert(role));
+        parse_listing::<User>(LSUSER, ':')
+            .unwrap()
+            .into_iter()
+            .for_each(|user| device.users.insert(user));
+        let roles = vec![role(2, "admins"), role(3, "auditors"), role(5, "monitors")];
+        let reconcile = |device: &Device| {
+            let mut plan = Plan::new();
+            let users = device.users.objects().cloned().collect::<Vec<_>>();
+            let actual_roles = device.roles.objects().cloned().collect::<Vec<_>>();
+            plan.reconcile(&desired(), &users, &target()).unwrap();
+            plan.reconcile(&roles, &actual_roles, &target()).unwrap();
+            plan
+        };
+
+        reconcile(&device).apply(&mut device).unwrap();
+
+        let role_ids = device
+            .roles
+            .objects()
+            .map(|role| *role.id)
+            .collect::<Vec<_>>();
+        assert_eq!(role_ids, vec![2, 3, 5]);
+        assert_eq!(*device.users.get(48).unwrap().role_id, Ref::new(3));
+        assert!(reconcile(&device).is_empty());
+    }
+
+    #[system_object(modify = "update_node", create = "make_node", delete = "remove_node")]
+    struct Node {
+        #[field(id, create_param = "-id")]
+        id: u32,
+        #[field(param = "-set_parent", create_param = "-parent")]
+        parent: Ref<Node>,
+    }
+
+    fn node(id: u32, parent: u32) -> Node {
+        let mut node = Node::default();
+        *node.id = id;
+        *node.parent = Ref::new(parent);
+        node
+    }
+
+    #[test]
+    fn reference_cycles_are_rejected() {
+        let before = plan();
+        let mut plan = before.clone();
+
+        let error = plan
+            .reconcile(&[node(1, 2), node(2, 1), node(3, 3)], &[], &target())
+            .unwrap_err();
+
+        let ReconcileError::Cycle(cycle) = error else {
+            panic!("expected a cycle, got {}", error);
+        };
+        assert_eq!(
+            cycle.to_string(),
+            "dependency cycle: Node 2 -> Node 1 -> Node 2"
+        );
+        assert_eq!(plan, before);
+    }
+
+    #[test]
+    fn creates_need_an_id_they_can_set() {
+        let mut plan = Plan::new();
+
+        let error = plan
+            .reconcile(&[user(0, "erin", 3)], &[], &target())
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            ReconcileError::Command(CommandError::IdNotSettable {
+                object_type: "User",
+                object_id: 0
+            })
+        ));
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let mut plan = Plan::new();
+        let users = [user(45, "alice", 2), user(45, "carol", 2)];
+
+        for (desired, actual) in [(&users[..], &[][..]), (&[][..], &users[..])] {
+            let error = plan.reconcile(desired, actual, &target()).unwrap_err();
+
+            assert!(matches!(error, ReconcileError::DuplicateId(_)));
+            assert_eq!(error.to_string(), "User 45 is listed more than once");
+        }
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn objects_may_refer_to_themselves() {
+        let mut plan = Plan::new();
+
+        plan.reconcile(&[node(3, 3), node(4, 3)], &[], &target())
+            .unwrap();
+
+        let ids = plan
+            .entries()
+            .iter()
+            .map(PlanEntry::object_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4]);
+    }
+
+    #[test]
+    fn long_reference_chains_are_ordered() {
+        let create = |id: u32| PlanEntry {
+            object: ObjectReference::new::<Node>(id),
+            command: ReversibleCommand::new(
+                ModificationCommand::new(CommandType::Create, "make_node", None, Vec::new()),
+                ModificationCommand::new(CommandType::Delete, "remove_node", None, Vec::new()),
+            ),
+            changes: Vec::new(),
+            version_decisions: Vec::new(),
+            requires: vec![ObjectReference::new::<Node>(id + 1)],
+            releases: Vec::new(),
+        };
+
+        let entries = order_by_dependencies((1..20_000).map(create).collect()).unwrap();
+
+        assert!(entries
+            .iter()
+            .map(PlanEntry::object_id)
+            .eq((1..20_000).rev()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn desired_state_can_come_from_yaml() {
+        let desired: Vec<User> = serde_yaml::from_str(
+            "
+- id: 45
+  name: bob smith
+  role_id: 2
+- id: 46
+  name: carol
+  role_id: 5
+- id: 48
+  name: erin
+  role_id: 3
+",
+        )
+        .unwrap();
+        let actual = parse_listing::<User>(LSUSER, ':').unwrap();
+        let mut from_yaml = Plan::new();
+
+        from_yaml.reconcile(&desired, &actual, &target()).unwrap();
+
+        assert_eq!(from_yaml, plan());
+    }
+}
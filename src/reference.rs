@@ -0,0 +1,188 @@
+//! Typed references from one object's fields to other objects, so that plans can order
+//! commands by their dependencies.
+
+use std::{any::TypeId, fmt, marker::PhantomData};
+
+use bevy_reflect::Reflect;
+
+use crate::internal::{
+    short_type_name, AsReference, FormatArgument, IsEmpty, ParseArgument, ParseArgumentError,
+    SystemObject,
+};
+
+/// The id of an object of type `T`, e.g. a `Ref<Role>` field holds the id of a role.
+/// A reference can be empty; it renders as an empty argument and is reset by reset
+/// flags.
+#[derive(Reflect)]
+#[reflect_value(PartialEq)]
+pub struct Ref<T: SystemObject> {
+    id: Option<u32>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: SystemObject> Ref<T> {
+    pub fn new(id: u32) -> Self {
+        Ref {
+            id: Some(id),
+            marker: PhantomData,
+        }
+    }
+
+    /// A reference to no object.
+    pub fn none() -> Self {
+        Ref {
+            id: None,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+}
+
+impl<T: SystemObject> From<u32> for Ref<T> {
+    fn from(id: u32) -> Self {
+        Ref::new(id)
+    }
+}
+
+impl<T: SystemObject> Default for Ref<T> {
+    fn default() -> Self {
+        Ref::none()
+    }
+}
+
+impl<T: SystemObject> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: SystemObject> Copy for Ref<T> {}
+
+impl<T: SystemObject> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T: SystemObject> Eq for Ref<T> {}
+
+impl<T: SystemObject> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "Ref<{}>({})", short_type_name::<T>(), id),
+            None => write!(f, "Ref<{}>(none)", short_type_name::<T>()),
+        }
+    }
+}
+
+impl<T: SystemObject> IsEmpty for Ref<T> {
+    fn is_empty(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+impl<T: SystemObject> FormatArgument for Ref<T> {
+    fn format_argument(&self) -> String {
+        self.id.map(|id| id.to_string()).unwrap_or_default()
+    }
+}
+
+impl<T: SystemObject> ParseArgument for Ref<T> {
+    fn parse_argument(argument: &str) -> Result<Self, ParseArgumentError> {
+        if argument.is_empty() {
+            return Ok(Ref::none());
+        }
+        u32::parse_argument(argument).map(Ref::new)
+    }
+}
+
+impl<T: SystemObject> AsReference for Ref<T> {
+    fn as_reference(&self) -> Option<ObjectReference> {
+        self.id.map(ObjectReference::new::<T>)
+    }
+}
+
+/// An object identified by its type and id, the way plan entries identify theirs. Types
+/// are told apart by their [`TypeId`], so types of the same name in different modules
+/// don't collide; the type name is only kept for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectReference {
+    type_id: TypeId,
+    object_type: &'static str,
+    object_id: u32,
+}
+
+impl ObjectReference {
+    /// The object of type `T` with id `object_id`.
+    pub fn new<T: 'static>(object_id: u32) -> Self {
+        ObjectReference {
+            type_id: TypeId::of::<T>(),
+            object_type: short_type_name::<T>(),
+            object_id,
+        }
+    }
+
+    /// The object's type name, e.g. `User`.
+    pub fn object_type(&self) -> &'static str {
+        self.object_type
+    }
+
+    pub fn object_id(&self) -> u32 {
+        self.object_id
+    }
+}
+
+impl fmt::Display for ObjectReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.object_type, self.object_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::SystemObject;
+    use crate::{Role, User};
+
+    #[test]
+    fn references_round_trip_as_arguments() {
+        let role = Ref::<Role>::new(3);
+
+        assert_eq!(role.format_argument(), "3");
+        assert_eq!(Ref::<Role>::parse_argument("3"), Ok(role));
+        assert_eq!(Ref::<Role>::parse_argument(""), Ok(Ref::none()));
+        assert!(Ref::<Role>::none().is_empty());
+        assert_eq!(
+            Ref::<Role>::parse_argument("admin").unwrap_err().expected,
+            "u32"
+        );
+    }
+
+    #[test]
+    fn references_tell_types_of_the_same_name_apart() {
+        mod other {
+            pub struct Role;
+        }
+
+        assert_ne!(
+            ObjectReference::new::<Role>(3),
+            ObjectReference::new::<other::Role>(3)
+        );
+        assert_eq!(ObjectReference::new::<other::Role>(3).to_string(), "Role 3");
+    }
+
+    #[test]
+    fn objects_list_the_objects_they_refer_to() {
+        let mut user = User::default();
+        *user.id = 45;
+        assert!(user.references().is_empty());
+
+        *user.role_id = Ref::new(3);
+
+        assert_eq!(user.references(), vec![ObjectReference::new::<Role>(3)]);
+        assert_eq!(user.references()[0].to_string(), "Role 3");
+    }
+}
@@ -0,0 +1,197 @@
+//! Registering system objects with a bevy_reflect [`TypeRegistry`], so that tools can
+//! work with objects through reflection alone. With the `ron` feature, registered objects
+//! can be saved to and loaded from RON without knowing their types at compile time.
+
+use bevy_reflect::{
+    std_traits::ReflectDefault, GetTypeRegistration, Reflect, ReflectMut, ReflectRef, TypeRegistry,
+};
+#[cfg(feature = "serde")]
+use bevy_reflect::{ReflectDeserialize, ReflectSerialize};
+
+use crate::internal::{ReflectTrackedField, SoftwareVersion, SystemObject};
+
+/// Registers the types shared by every system object.
+pub fn register_common(registry: &mut TypeRegistry) {
+    registry.register::<String>();
+    registry.register::<SoftwareVersion>();
+    #[cfg(feature = "serde")]
+    {
+        use crate::internal::VersionFilter;
+
+        registry.register::<VersionFilter>();
+        registry.register_type_data::<VersionFilter, ReflectSerialize>();
+        registry.register_type_data::<VersionFilter, ReflectDeserialize>();
+    }
+}
+
+/// Registers `ObjectType`, its fields, and its `Default` so that [`load_into_default`]
+/// can build it from a dynamic value.
+pub fn register_object<ObjectType>(registry: &mut TypeRegistry)
+where
+    ObjectType: SystemObject + GetTypeRegistration + Default,
+{
+    registry.register::<ObjectType>();
+    registry.register_type_data::<ObjectType, ReflectDefault>();
+    ObjectType::register_fields(registry);
+}
+
+/// Builds a registered object from `value`, usually the dynamic struct produced by
+/// bevy_reflect's deserializer: starts from the object's default, so every field keeps
+/// its metadata, and copies over the value and baseline of each member present in
+/// `value`. Returns `value` itself if its type isn't registered with a default.
+pub fn load_into_default(registry: &TypeRegistry, value: Box<dyn Reflect>) -> Box<dyn Reflect> {
+    let Some(default) = registry
+        .get_with_name(value.type_name())
+        .and_then(|registration| registration.data::<ReflectDefault>())
+    else {
+        return value;
+    };
+    let mut object = default.default();
+
+    if let (ReflectRef::Struct(source), ReflectMut::Struct(target)) =
+        (value.reflect_ref(), object.reflect_mut())
+    {
+        for (index, member) in source.iter_fields().enumerate() {
+            let Some(name) = source.name_at(index) else {
+                continue;
+            };
+            let Some(target_member) = target.field_mut(name) else {
+                continue;
+            };
+            let restored = registry
+                .get_type_data::<ReflectTrackedField>(target_member.as_any().type_id())
+                .and_then(|tracked| tracked.get_mut(target_member))
+                .map(|tracked| tracked.restore_from(member));
+            if restored.is_none() {
+                target_member.apply(member);
+            }
+        }
+    }
+    object
+}
+
+#[cfg(feature = "ron")]
+pub use self::ron_format::{load_ron, save_ron};
+
+#[cfg(feature = "ron")]
+mod ron_format {
+    use bevy_reflect::{
+        serde::{ReflectDeserializer, ReflectSerializer},
+        Reflect, TypeRegistry,
+    };
+    use serde::de::DeserializeSeed;
+
+    use super::load_into_default;
+
+    /// Writes any registered value as RON, tagged with its type name.
+    pub fn save_ron(value: &dyn Reflect, registry: &TypeRegistry) -> Result<String, ron::Error> {
+        ron::ser::to_string_pretty(
+            &ReflectSerializer::new(value, registry),
+            ron::ser::PrettyConfig::default(),
+        )
+    }
+
+    /// Reads a value written by [`save_ron`]. Objects registered with
+    /// [`register_object`](super::register_object) come back as their concrete type;
+    /// anything else comes back as a dynamic value.
+    pub fn load_ron(ron: &str, registry: &TypeRegistry) -> Result<Box<dyn Reflect>, ron::Error> {
+        let mut deserializer = ron::Deserializer::from_str(ron).map_err(|error| error.code)?;
+        let value = ReflectDeserializer::new(registry).deserialize(&mut deserializer)?;
+        Ok(load_into_default(registry, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{Field, TrackedField, VersionFilter};
+    use crate::reference::Ref;
+    #[cfg(feature = "ron")]
+    use crate::test_support::stored_user;
+    use crate::{type_registry, Role, User, UserFields};
+    use bevy_reflect::DynamicStruct;
+
+    #[test]
+    fn registry_knows_objects_and_their_fields() {
+        let registry = type_registry();
+
+        assert!(registry.get(std::any::TypeId::of::<User>()).is_some());
+        assert!(registry
+            .get(std::any::TypeId::of::<Field<u32, UserFields>>())
+            .is_some());
+        assert!(registry
+            .get(std::any::TypeId::of::<SoftwareVersion>())
+            .is_some());
+    }
+
+    #[test]
+    fn dynamic_values_load_into_default_objects() {
+        let registry = type_registry();
+        let mut role_id = Field::<Ref<Role>, UserFields>::new("", UserFields::default());
+        role_id.set(Ref::new(7));
+        let mut dynamic = DynamicStruct::default();
+        dynamic.set_name(String::from(std::any::type_name::<User>()));
+        dynamic.insert("role_id", role_id);
+
+        let loaded = load_into_default(&registry, Box::new(dynamic));
+        let user = loaded.downcast_ref::<User>().unwrap();
+
+        assert_eq!(*user.role_id, Ref::new(7));
+        assert!(user.role_id.is_changed());
+        assert_eq!(TrackedField::field_name(&user.role_id), "role_id");
+        assert_eq!(
+            user.role_id.version_filter(),
+            Some(&VersionFilter::min_version(SoftwareVersion::new(4, 12)))
+        );
+    }
+
+    #[cfg(feature = "ron")]
+    #[test]
+    fn objects_round_trip_through_ron() {
+        let registry = type_registry();
+        let user = stored_user();
+
+        let ron = save_ron(&user, &registry).unwrap();
+        let loaded = load_ron(&ron, &registry).unwrap();
+        let loaded = loaded.downcast_ref::<User>().unwrap();
+
+        assert_eq!(*loaded.id, 45);
+        assert_eq!(*loaded.name, "bob");
+        assert_eq!(*loaded.role_id, Ref::new(2));
+        assert!(!loaded.is_changed());
+    }
+
+    #[cfg(feature = "ron")]
+    #[test]
+    fn versions_round_trip_through_ron() {
+        let registry = type_registry();
+        let version = SoftwareVersion::new(8, 5).with_build(3);
+
+        let loaded = load_ron(&save_ron(&version, &registry).unwrap(), &registry).unwrap();
+
+        assert!(loaded.reflect_partial_eq(&version).unwrap_or(false));
+    }
+
+    #[cfg(feature = "ron")]
+    #[test]
+    fn unregistered_defaults_load_as_dynamic_values() {
+        let mut registry = type_registry();
+        let ron = save_ron(&stored_user(), &registry).unwrap();
+        registry = TypeRegistry::default();
+        register_common(&mut registry);
+        User::register_fields(&mut registry);
+
+        let loaded = load_ron(&ron, &registry).unwrap();
+
+        assert!(loaded.downcast_ref::<User>().is_none());
+        let ReflectRef::Struct(user) = loaded.reflect_ref() else {
+            panic!("expected a struct");
+        };
+        let name = user.field("name").unwrap();
+        assert_eq!(
+            name.downcast_ref::<Field<String, UserFields>>()
+                .map(|name| name.get().as_str()),
+            Some("bob")
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::{borrow::Cow, error::Error, fmt, process::Command};
+
+use crate::internal::{ModificationCommand, Parameter};
+
+/// How arguments are quoted when a command is written out as a single line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// POSIX shell quoting: arguments that need it are wrapped in single quotes, and
+    /// embedded single quotes are written as `'\''`.
+    #[default]
+    Posix,
+    /// The quoting understood by the target system's own CLI: arguments that need it are
+    /// wrapped in `quote`, and any `quote` or `escape` characters inside them are
+    /// preceded by `escape`.
+    DeviceCli { quote: char, escape: char },
+}
+
+impl QuoteStyle {
+    pub const fn device_cli(quote: char, escape: char) -> Self {
+        QuoteStyle::DeviceCli { quote, escape }
+    }
+
+    /// Quotes a single argument, leaving it untouched if it is made up only of characters
+    /// that every supported CLI treats literally.
+    pub fn quote<'a>(&self, argument: &'a str) -> Cow<'a, str> {
+        if !argument.is_empty() && argument.chars().all(is_safe_char) {
+            return Cow::Borrowed(argument);
+        }
+
+        match *self {
+            QuoteStyle::Posix => Cow::Owned(format!("'{}'", argument.replace('\'', "'\\''"))),
+            QuoteStyle::DeviceCli { quote, escape } => {
+                let mut quoted = String::with_capacity(argument.len() + 2);
+                quoted.push(quote);
+                for c in argument.chars() {
+                    if c == quote || c == escape {
+                        quoted.push(escape);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push(quote);
+                Cow::Owned(quoted)
+            }
+        }
+    }
+
+    /// Splits a command line into arguments, undoing [`QuoteStyle::quote`]. Arguments are
+    /// separated by unquoted whitespace. With [`QuoteStyle::Posix`], double quotes and
+    /// backslashes are understood as a shell would, so hand-written lines parse too.
+    pub fn split(&self, line: &str) -> Result<Vec<String>, SplitError> {
+        let mut arguments = Vec::new();
+        let mut current: Option<String> = None;
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                arguments.extend(current.take());
+                continue;
+            }
+            let argument = current.get_or_insert_with(String::new);
+            match *self {
+                QuoteStyle::Posix => match c {
+                    '\\' => argument.push(chars.next().ok_or(SplitError::TrailingEscape)?),
+                    '\'' => loop {
+                        match chars.next().ok_or(SplitError::UnterminatedQuote)? {
+                            '\'' => break,
+                            c => argument.push(c),
+                        }
+                    },
+                    '"' => loop {
+                        match chars.next().ok_or(SplitError::UnterminatedQuote)? {
+                            '"' => break,
+                            '\\' => {
+                                let escaped = chars.next().ok_or(SplitError::UnterminatedQuote)?;
+                                if !matches!(escaped, '"' | '\\' | '$' | '`') {
+                                    argument.push('\\');
+                                }
+                                argument.push(escaped);
+                            }
+                            c => argument.push(c),
+                        }
+                    },
+                    c => argument.push(c),
+                },
+                QuoteStyle::DeviceCli { quote, escape } if c == quote => loop {
+                    match chars.next().ok_or(SplitError::UnterminatedQuote)? {
+                        // When quotes are escaped by doubling them, a quote ends the
+                        // argument unless another one follows.
+                        c if c == escape && c == quote => {
+                            if chars.next_if_eq(&quote).is_none() {
+                                break;
+                            }
+                            argument.push(quote);
+                        }
+                        c if c == escape => {
+                            argument.push(chars.next().ok_or(SplitError::UnterminatedQuote)?)
+                        }
+                        c if c == quote => break,
+                        c => argument.push(c),
+                    }
+                },
+                QuoteStyle::DeviceCli { .. } => argument.push(c),
+            }
+        }
+        arguments.extend(current);
+        Ok(arguments)
+    }
+}
+
+/// Why a command line could not be split into arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitError {
+    UnterminatedQuote,
+    /// The line ends with an escape character that has nothing to escape.
+    TrailingEscape,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::UnterminatedQuote => write!(f, "unterminated quote"),
+            SplitError::TrailingEscape => write!(f, "trailing escape character"),
+        }
+    }
+}
+
+impl Error for SplitError {}
+
+fn is_safe_char(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+        || matches!(c, '-' | '_' | '.' | ',' | ':' | '/' | '@' | '%' | '+' | '=')
+}
+
+impl ModificationCommand {
+    /// The command as separate arguments, ready to hand to [`std::process::Command`].
+    /// The object id, when there is one, is always the last argument.
+    pub fn to_argv(&self) -> Vec<String> {
+        let mut argv = vec![String::from(self.command())];
+        for argument in self.arguments() {
+            argv.push(String::from(argument.name()));
+            if let Parameter::Parameter(_, value) = argument {
+                argv.push(value.clone());
+            }
+        }
+        if let Some(object_id) = self.object_id() {
+            argv.push(String::from(object_id));
+        }
+        argv
+    }
+
+    /// The command as one line, with every argument quoted according to `style`.
+    pub fn to_command_line(&self, style: QuoteStyle) -> String {
+        self.to_argv()
+            .iter()
+            .map(|argument| style.quote(argument))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl From<&ModificationCommand> for Command {
+    fn from(command: &ModificationCommand) -> Self {
+        let argv = command.to_argv();
+        let mut process = Command::new(&argv[0]);
+        process.args(&argv[1..]);
+        process
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::CommandType;
+    use crate::test_support::target;
+    use crate::User;
+
+    fn renamed_user(name: &str) -> ModificationCommand {
+        let mut user = User::default();
+        *user.id = 45;
+        *user.name = String::from(name);
+        ModificationCommand::modify(&user, &target()).unwrap()
+    }
+
+    #[test]
+    fn argv_puts_object_id_last() {
+        let command = renamed_user("bob smith");
+
+        assert_eq!(
+            command.to_argv(),
+            vec!["update_user", "-set_name", "bob smith", "45"]
+        );
+    }
+
+    #[test]
+    fn argv_omits_values_for_flags() {
+        let command = ModificationCommand::new(
+            CommandType::Modify,
+            "update_user",
+            Some(String::from("45")),
+            vec![Parameter::Flag("-reset_name")],
+        );
+
+        assert_eq!(command.to_argv(), vec!["update_user", "-reset_name", "45"]);
+    }
+
+    #[test]
+    fn posix_quotes_spaces_and_single_quotes() {
+        let command = renamed_user("bob o'neil");
+
+        assert_eq!(
+            command.to_command_line(QuoteStyle::Posix),
+            r#"update_user -set_name 'bob o'\''neil' 45"#
+        );
+    }
+
+    #[test]
+    fn posix_quotes_non_ascii_and_empty_arguments() {
+        assert_eq!(QuoteStyle::Posix.quote("Zoë"), "'Zoë'");
+        assert_eq!(QuoteStyle::Posix.quote(""), "''");
+        assert_eq!(QuoteStyle::Posix.quote("plain"), "plain");
+    }
+
+    #[test]
+    fn device_cli_escapes_quote_and_escape_characters() {
+        let style = QuoteStyle::device_cli('"', '\\');
+        let command = renamed_user(r#"bob "the \ builder""#);
+
+        assert_eq!(
+            command.to_command_line(style),
+            r#"update_user -set_name "bob \"the \\ builder\"" 45"#
+        );
+    }
+
+    #[test]
+    fn device_cli_uses_configured_characters() {
+        let style = QuoteStyle::device_cli('\'', '^');
+
+        assert_eq!(style.quote("it's ^ok"), "'it^'s ^^ok'");
+    }
+
+    #[test]
+    fn split_undoes_quoting() {
+        let command = renamed_user(r#"bob "o'neil" \ jr"#);
+
+        for style in [
+            QuoteStyle::Posix,
+            QuoteStyle::device_cli('"', '\\'),
+            QuoteStyle::device_cli('\'', '\''),
+        ] {
+            assert_eq!(
+                style.split(&command.to_command_line(style)).unwrap(),
+                command.to_argv()
+            );
+        }
+        assert_eq!(
+            QuoteStyle::device_cli('\'', '\'')
+                .split("chuser -name 'it''s' ''")
+                .unwrap(),
+            vec!["chuser", "-name", "it's", ""]
+        );
+    }
+
+    #[test]
+    fn posix_split_understands_hand_written_lines() {
+        assert_eq!(
+            QuoteStyle::Posix
+                .split(r#"update_user  -set_name "bob smith" -set_roleid 3 45"#)
+                .unwrap(),
+            vec![
+                "update_user",
+                "-set_name",
+                "bob smith",
+                "-set_roleid",
+                "3",
+                "45"
+            ]
+        );
+        assert_eq!(
+            QuoteStyle::Posix.split(r#"a\ b "c\"d" '' "e\f""#).unwrap(),
+            vec!["a b", "c\"d", "", "e\\f"]
+        );
+    }
+
+    #[test]
+    fn split_rejects_unterminated_quotes() {
+        assert_eq!(
+            QuoteStyle::Posix.split("update_user -set_name 'bob"),
+            Err(SplitError::UnterminatedQuote)
+        );
+        assert_eq!(
+            QuoteStyle::Posix.split("update_user \\"),
+            Err(SplitError::TrailingEscape)
+        );
+    }
+}
@@ -0,0 +1,486 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! Fields serialize as their bare values, so a stored object looks like the plain struct
+//! it was declared as. The metadata of each field (its name, parameters and version
+//! filter) isn't stored; deserializing an object starts from its `Default` and fills in
+//! the values.
+//!
+//! Plans and commands only serialize, as documents for the tools that review them
+//! before they are applied.
+
+use std::{fmt::Display, str::FromStr};
+
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::internal::{
+    CommandType, Field, FieldChange, FieldDataType, FieldEnumType, ModificationCommand, Parameter,
+    SoftwareVersion, SystemObject, VersionDecision, VersionFilter,
+};
+use crate::plan::{Plan, PlanEntry};
+use crate::reference::Ref;
+use crate::render::QuoteStyle;
+
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Serializes as a version string such as `"4.12"`.
+impl Serialize for SoftwareVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SoftwareVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// Serializes as a filter expression such as `">=4.12, <5.0"`.
+impl Serialize for VersionFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// Serializes as the referenced id, or as none for an empty reference.
+impl<T: SystemObject> Serialize for Ref<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id().serialize(serializer)
+    }
+}
+
+impl<'de, T: SystemObject> Deserialize<'de> for Ref<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::<u32>::deserialize(deserializer)?.map_or_else(Ref::none, Ref::new))
+    }
+}
+
+/// Serializes as the bare value. Use [`Field::with_state`] to include the baseline.
+impl<T: FieldDataType + Serialize, FieldEnum: FieldEnumType> Serialize for Field<T, FieldEnum> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+/// Deserializes a detached field with no name or version filter, from either form
+/// accepted by [`StoredField`]. Objects don't use this; it exists so that fields can be
+/// registered with bevy_reflect's deserializer, whose output is restored into the
+/// fields of a default object.
+impl<'de, T, FieldEnum> Deserialize<'de> for Field<T, FieldEnum>
+where
+    T: FieldDataType + DeserializeOwned,
+    FieldEnum: FieldEnumType,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut field = Field::new("", FieldEnum::default());
+        StoredField::deserialize(deserializer)?.restore_into(&mut field);
+        Ok(field)
+    }
+}
+
+/// A field's value together with its baseline, if it has one, and whether it is changed.
+#[derive(Debug, Serialize)]
+pub struct FieldState<'a, T> {
+    value: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline: Option<&'a T>,
+    changed: bool,
+}
+
+impl<T: FieldDataType, FieldEnum: FieldEnumType> Field<T, FieldEnum> {
+    pub fn with_state(&self) -> FieldState<'_, T> {
+        FieldState {
+            value: self.get(),
+            baseline: self.baseline_value(),
+            changed: self.is_changed(),
+        }
+    }
+}
+
+/// A field as read back from storage: either the bare value, which leaves the field
+/// unchanged, or a [`FieldState`], which restores its baseline too. Missing fields keep
+/// their default value.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StoredField<T> {
+    State {
+        value: T,
+        #[serde(default)]
+        baseline: Option<T>,
+    },
+    Value(T),
+}
+
+impl<T: Default> Default for StoredField<T> {
+    fn default() -> Self {
+        StoredField::Value(T::default())
+    }
+}
+
+impl<T: FieldDataType> StoredField<T> {
+    pub fn restore_into<FieldEnum: FieldEnumType>(self, field: &mut Field<T, FieldEnum>) {
+        match self {
+            StoredField::State { value, baseline } => field.restore(value, baseline),
+            StoredField::Value(value) => field.restore(value, None),
+        }
+    }
+}
+
+/// Objects that can be serialized with the state of every field, see [`WithState`].
+/// Implemented by `#[system_object]`.
+pub trait SerializeWithState {
+    fn serialize_with_state<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+}
+
+/// Serializes an object with each field written as a [`FieldState`], so that pending
+/// changes survive a round trip.
+pub struct WithState<'a, ObjectType>(pub &'a ObjectType);
+
+impl<ObjectType: SerializeWithState> Serialize for WithState<'_, ObjectType> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_with_state(serializer)
+    }
+}
+
+/// The version of the JSON layout of [`Plan`]s, bumped whenever a field changes meaning
+/// or is removed.
+pub const PLAN_FORMAT_VERSION: u32 = 1;
+
+/// Serializes as `{"format_version": 1, "entries": [...]}`, for tools that review plans
+/// before they are applied. Each entry has the object type and id, the command type, the
+/// changed fields, the version filter decisions, and the command lines that apply and
+/// back out the change. [`Plan::skipped`] modifies follow under `"skipped"`, when there
+/// are any, without command lines.
+impl Serialize for Plan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct PlanDocument<'a> {
+            format_version: u32,
+            entries: Vec<EntryDocument<'a>>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            skipped: Vec<SkippedDocument<'a>>,
+        }
+
+        #[derive(Serialize)]
+        struct SkippedDocument<'a> {
+            object_type: &'a str,
+            object_id: u32,
+            changes: &'a [FieldChange],
+            version_decisions: &'a [VersionDecision],
+        }
+
+        PlanDocument {
+            format_version: PLAN_FORMAT_VERSION,
+            entries: self.entries().iter().map(EntryDocument::from).collect(),
+            skipped: self
+                .skipped()
+                .iter()
+                .map(|entry| SkippedDocument {
+                    object_type: entry.object_type(),
+                    object_id: entry.object_id(),
+                    changes: entry.changes(),
+                    version_decisions: entry.version_decisions(),
+                })
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[derive(Serialize)]
+struct EntryDocument<'a> {
+    object_type: &'a str,
+    object_id: u32,
+    command_type: CommandType,
+    changes: &'a [FieldChange],
+    version_decisions: &'a [VersionDecision],
+    command: String,
+    inverse_command: String,
+}
+
+impl<'a> From<&'a PlanEntry> for EntryDocument<'a> {
+    fn from(entry: &'a PlanEntry) -> Self {
+        EntryDocument {
+            object_type: entry.object_type(),
+            object_id: entry.object_id(),
+            command_type: entry.command_type(),
+            changes: entry.changes(),
+            version_decisions: entry.version_decisions(),
+            command: entry.command().to_command_line(QuoteStyle::Posix),
+            inverse_command: entry.inverse().to_command_line(QuoteStyle::Posix),
+        }
+    }
+}
+
+/// Serializes with its arguments and the command line they render to.
+impl Serialize for ModificationCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct CommandDocument<'a> {
+            command_type: CommandType,
+            command: &'a str,
+            object_id: Option<&'a str>,
+            arguments: &'a [Parameter],
+            command_line: String,
+        }
+
+        CommandDocument {
+            command_type: self.command_type(),
+            command: self.command(),
+            object_id: self.object_id(),
+            arguments: self.arguments(),
+            command_line: self.to_command_line(QuoteStyle::Posix),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Serializes as `{"name": ..., "value": ...}`, without a value for flags.
+impl Serialize for Parameter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct ParameterDocument<'a> {
+            name: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            value: Option<&'a str>,
+        }
+
+        ParameterDocument {
+            name: self.name(),
+            value: self.value(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Registers bevy_reflect's serde type data for a field type, so that the reflection
+/// serializer can write it. `#[system_object]` calls this for each of its field types.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! register_field_serde {
+    ($registry:ident, $field:ty) => {
+        $registry.register_type_data::<$field, ::bevy_reflect::ReflectSerialize>();
+        $registry.register_type_data::<$field, ::bevy_reflect::ReflectDeserialize>();
+    };
+}
+
+/// Implements `Serialize`, `Deserialize` and [`SerializeWithState`] for a system object.
+/// `#[system_object]` calls this with each member and its value type.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! impl_object_serde {
+    ($object:ident { $($field:ident: $ty:ty),* $(,)? }) => {
+        impl $crate::serde::Serialize for $object {
+            fn serialize<S: $crate::serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> ::std::result::Result<S::Ok, S::Error> {
+                use $crate::serde::ser::SerializeStruct;
+                let len = [$(stringify!($field)),*].len();
+                let mut state = serializer.serialize_struct(stringify!($object), len)?;
+                $(state.serialize_field(stringify!($field), &self.$field)?;)*
+                state.end()
+            }
+        }
+
+        impl $crate::serialization::SerializeWithState for $object {
+            fn serialize_with_state<S: $crate::serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> ::std::result::Result<S::Ok, S::Error> {
+                use $crate::serde::ser::SerializeStruct;
+                let len = [$(stringify!($field)),*].len();
+                let mut state = serializer.serialize_struct(stringify!($object), len)?;
+                $(state.serialize_field(stringify!($field), &self.$field.with_state())?;)*
+                state.end()
+            }
+        }
+
+        impl<'de> $crate::serde::Deserialize<'de> for $object {
+            fn deserialize<D: $crate::serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> ::std::result::Result<Self, D::Error> {
+                #[derive(Default, $crate::serde::Deserialize)]
+                #[serde(crate = "::fancy_stuff_with_reflection::serde", default)]
+                struct Stored {
+                    $($field: $crate::serialization::StoredField<$ty>,)*
+                }
+
+                let stored = <Stored as $crate::serde::Deserialize>::deserialize(deserializer)?;
+                let mut object = <Self as ::std::default::Default>::default();
+                $(stored.$field.restore_into(&mut object.$field);)*
+                Ok(object)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::SystemObject;
+    use crate::test_support::{stored, stored_user, user};
+    use crate::User;
+
+    fn assert_same_values(user: &User, expected: &User) {
+        assert_eq!(*user.id, *expected.id);
+        assert_eq!(*user.name, *expected.name);
+        assert_eq!(*user.role_id, *expected.role_id);
+    }
+
+    #[test]
+    fn objects_serialize_as_plain_values() {
+        let json = serde_json::to_string(&stored_user()).unwrap();
+
+        assert_eq!(json, r#"{"id":45,"name":"bob","role_id":2}"#);
+    }
+
+    #[test]
+    fn objects_round_trip_through_json_yaml_and_toml() {
+        let user = stored_user();
+
+        let from_json: User = serde_json::from_str(&serde_json::to_string(&user).unwrap()).unwrap();
+        let from_yaml: User = serde_yaml::from_str(&serde_yaml::to_string(&user).unwrap()).unwrap();
+        let from_toml: User = toml::from_str(&toml::to_string(&user).unwrap()).unwrap();
+
+        for loaded in [&from_json, &from_yaml, &from_toml] {
+            assert_same_values(loaded, &user);
+            assert!(!loaded.is_changed());
+        }
+    }
+
+    #[test]
+    fn loaded_objects_keep_field_metadata() {
+        let user: User = toml::from_str("id = 45\nname = \"bob\"").unwrap();
+
+        assert_eq!(*user.role_id, Ref::none());
+        assert_eq!(
+            user.role_id.version_filter(),
+            Some(&VersionFilter::min_version(SoftwareVersion::new(4, 12)))
+        );
+    }
+
+    #[test]
+    fn with_state_preserves_pending_changes() {
+        let mut user = stored_user();
+        *user.name = String::from("alice");
+
+        let json = serde_json::to_string(&WithState(&user)).unwrap();
+        let loaded: User = serde_json::from_str(&json).unwrap();
+
+        assert!(json.contains(r#""name":{"value":"alice","baseline":"bob","changed":true}"#));
+        assert_eq!(*loaded.name, "alice");
+        assert_eq!(
+            loaded.name.baseline_value().map(String::as_str),
+            Some("bob")
+        );
+        assert!(loaded.is_changed());
+    }
+
+    #[test]
+    fn plans_serialize_as_stable_json() {
+        use crate::internal::{TargetSystem, VersionPolicy};
+
+        let renamed = stored_user();
+        let mut desired_name = renamed.clone();
+        *desired_name.name = String::from("bob smith");
+        let stored = stored(user(46, "bob", 2));
+        let mut desired = stored.clone();
+        *desired.role_id = Ref::new(5);
+        let target = TargetSystem::new(SoftwareVersion::new(3, 188))
+            .with_version_policy(VersionPolicy::Drop);
+        let mut plan = Plan::new();
+        plan.reconcile(&[desired_name, desired], &[renamed, stored], &target)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&plan).unwrap(),
+            serde_json::json!({
+                "format_version": 1,
+                "entries": [{
+                    "object_type": "User",
+                    "object_id": 45,
+                    "command_type": "modify",
+                    "changes": [
+                        {"path": "name", "old_value": "bob", "new_value": "bob smith"}
+                    ],
+                    "version_decisions": [],
+                    "command": "update_user -set_name 'bob smith' 45",
+                    "inverse_command": "update_user -set_name bob 45"
+                }],
+                "skipped": [{
+                    "object_type": "User",
+                    "object_id": 46,
+                    "changes": [
+                        {"path": "role_id", "old_value": "2", "new_value": "5"}
+                    ],
+                    "version_decisions": [{
+                        "field": "role_id",
+                        "filter": ">=4.12",
+                        "version": "3.188",
+                        "outcome": "dropped"
+                    }]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn commands_serialize_with_their_command_line() {
+        let command = ModificationCommand::new(
+            CommandType::Modify,
+            "update_user",
+            Some(String::from("45")),
+            vec![
+                Parameter::Parameter("-set_name", String::from("bob smith")),
+                Parameter::Flag("-reset_name"),
+            ],
+        );
+
+        assert_eq!(
+            serde_json::to_value(&command).unwrap(),
+            serde_json::json!({
+                "command_type": "modify",
+                "command": "update_user",
+                "object_id": "45",
+                "arguments": [
+                    {"name": "-set_name", "value": "bob smith"},
+                    {"name": "-reset_name"}
+                ],
+                "command_line": "update_user -set_name 'bob smith' -reset_name 45"
+            })
+        );
+    }
+
+    #[test]
+    fn versions_and_filters_serialize_as_strings() {
+        let version = SoftwareVersion::new(8, 5).with_patch(0).with_build(3);
+        let filter: VersionFilter = ">=4.12, <5.0 || =3.188".parse().unwrap();
+
+        assert_eq!(serde_json::to_string(&version).unwrap(), r#""8.5.0.3""#);
+        assert_eq!(
+            serde_json::to_string(&filter).unwrap(),
+            r#"">=4.12, <5.0 || =3.188""#
+        );
+        assert_eq!(
+            serde_json::from_str::<VersionFilter>(r#"">=4.12, <5.0 || =3.188""#).unwrap(),
+            filter
+        );
+        assert!(serde_json::from_str::<SoftwareVersion>(r#""4.x""#).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! An in-process stand-in for a target system, so that the whole generate, execute and
+//! verify loop can be exercised without hardware. Commands are interpreted through the
+//! same object definitions and [`FieldParameter`](crate::internal::FieldParameter)
+//! mappings that generate them.
+
+use std::{collections::BTreeMap, io};
+
+use crate::executor::{CommandExecutor, CommandOutput};
+use crate::internal::{
+    short_type_name, CommandType, CreatableObject, DeletableObject, ModifiableObject,
+    ModificationCommand, Parameter, ParameterError, SoftwareVersion, SystemObject,
+};
+
+/// The error codes the simulator answers with, as the target system's CLI reports them.
+pub mod error_codes {
+    /// A parameter is unknown, or not supported by the system's version.
+    pub const UNSUPPORTED_PARAMETER: &str = "CMMVC5709E";
+    /// A required parameter, such as the object id, is missing.
+    pub const MISSING_PARAMETER: &str = "CMMVC5707E";
+    /// A parameter's value is not valid for its field.
+    pub const INVALID_VALUE: &str = "CMMVC5711E";
+    /// The object id does not name an existing object.
+    pub const NO_SUCH_OBJECT: &str = "CMMVC5753E";
+    /// A create command asks for an id that another object already has.
+    pub const OBJECT_EXISTS: &str = "CMMVC6035E";
+    /// A create command leaves the id to the system, but every id is taken.
+    pub const NO_FREE_ID: &str = "CMMVC5790E";
+}
+
+/// A simulated target system holding a table of objects of one type, keyed by id.
+#[derive(Debug, Clone)]
+pub struct SimulatedSystem<ObjectType> {
+    version: SoftwareVersion,
+    objects: BTreeMap<u32, ObjectType>,
+}
+
+impl<ObjectType> SimulatedSystem<ObjectType>
+where
+    ObjectType:
+        SystemObject + ModifiableObject + CreatableObject + DeletableObject + Default + Clone,
+{
+    pub fn new(version: SoftwareVersion) -> Self {
+        SimulatedSystem {
+            version,
+            objects: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_object(mut self, object: ObjectType) -> Self {
+        self.insert(object);
+        self
+    }
+
+    /// Stores `object` as it is, committed, replacing any object with the same id.
+    pub fn insert(&mut self, mut object: ObjectType) {
+        object.commit();
+        self.objects.insert(object.get_id(), object);
+    }
+
+    pub fn version(&self) -> SoftwareVersion {
+        self.version
+    }
+
+    pub fn get(&self, id: u32) -> Option<&ObjectType> {
+        self.objects.get(&id)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &ObjectType> {
+        self.objects.values()
+    }
+
+    /// Runs `command` against the table, answering the way the target system would.
+    pub fn run(&mut self, command: &ModificationCommand) -> CommandOutput {
+        let template = ObjectType::default();
+        let verb = match command.command_type() {
+            CommandType::Create => template.get_create_command(),
+            CommandType::Modify => template.get_modify_command(),
+            CommandType::Delete => template.get_delete_command(),
+        };
+        if command.command() != verb {
+            return CommandOutput::failure(
+                127,
+                format!("{}: command not found", command.command()),
+            );
+        }
+
+        let result = match command.command_type() {
+            CommandType::Create => self.create(command),
+            CommandType::Modify => self.modify(command),
+            CommandType::Delete => self.delete(command),
+        };
+        match result {
+            Ok(stdout) => CommandOutput::success(stdout),
+            Err((code, message)) => CommandOutput::failure(1, format!("{} {}", code, message)),
+        }
+    }
+
+    /// Creates the object under the id the command asks for, if it sets one, or else
+    /// under the next free id.
+    fn create(&mut self, command: &ModificationCommand) -> Result<String, Failure> {
+        if command.object_id().is_some() {
+            return Err(unsupported_parameter("object id"));
+        }
+
+        let mut object = ObjectType::default();
+        self.apply_arguments(&mut object, command)?;
+        let id = match command.sets_id(&object) {
+            true if self.objects.contains_key(&object.get_id()) => {
+                return Err((
+                    error_codes::OBJECT_EXISTS,
+                    String::from("The action failed as the object already exists."),
+                ))
+            }
+            true => object.get_id(),
+            false => match self.objects.keys().last() {
+                None => 0,
+                Some(id) => id.checked_add(1).ok_or((
+                    error_codes::NO_FREE_ID,
+                    String::from("The object was not created as no id is left to assign."),
+                ))?,
+            },
+        };
+        object.set_id(id);
+        self.insert(object);
+
+        Ok(format!(
+            "{}, id [{}], successfully created",
+            short_type_name::<ObjectType>(),
+            id
+        ))
+    }
+
+    fn modify(&mut self, command: &ModificationCommand) -> Result<String, Failure> {
+        let id = self.existing_id(command)?;
+        let mut object = self.objects[&id].clone();
+        self.apply_arguments(&mut object, command)?;
+        self.insert(object);
+        Ok(String::new())
+    }
+
+    fn delete(&mut self, command: &ModificationCommand) -> Result<String, Failure> {
+        let id = self.existing_id(command)?;
+        self.objects.remove(&id);
+        Ok(String::new())
+    }
+
+    fn existing_id(&self, command: &ModificationCommand) -> Result<u32, Failure> {
+        let Some(object_id) = command.object_id() else {
+            return Err((
+                error_codes::MISSING_PARAMETER,
+                String::from("Required parameters are missing."),
+            ));
+        };
+        object_id
+            .parse()
+            .ok()
+            .filter(|id| self.objects.contains_key(id))
+            .ok_or_else(|| {
+                (
+                    error_codes::NO_SUCH_OBJECT,
+                    String::from("The specified object does not exist."),
+                )
+            })
+    }
+
+    /// Applies every argument to `object`, rejecting switches that no field uses in this
+    /// command type or that the system's version doesn't support.
+    fn apply_arguments(
+        &self,
+        object: &mut ObjectType,
+        command: &ModificationCommand,
+    ) -> Result<(), Failure> {
+        let command_type = command.command_type();
+        for argument in command.arguments() {
+            let supported = object
+                .field_for_switch(command_type, argument.name())
+                .is_some_and(|field| {
+                    field
+                        .version_filter()
+                        .is_none_or(|filter| filter.matches(self.version))
+                });
+            if !supported {
+                return Err(unsupported_parameter(argument.name()));
+            }
+
+            object
+                .apply_parameter(command_type, argument)
+                .map_err(|error| match error {
+                    ParameterError::Unknown(name) | ParameterError::UnexpectedValue(name) => {
+                        unsupported_parameter(&name)
+                    }
+                    ParameterError::MissingValue(_) => (
+                        error_codes::MISSING_PARAMETER,
+                        String::from("Required parameters are missing."),
+                    ),
+                    ParameterError::InvalidValue(..) => (
+                        error_codes::INVALID_VALUE,
+                        format!("[{}] is not valid data.", value_of(argument)),
+                    ),
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl<ObjectType> CommandExecutor for SimulatedSystem<ObjectType>
+where
+    ObjectType:
+        SystemObject + ModifiableObject + CreatableObject + DeletableObject + Default + Clone,
+{
+    fn execute(&mut self, command: &ModificationCommand) -> io::Result<CommandOutput> {
+        Ok(self.run(command))
+    }
+}
+
+/// An error code and the message that follows it.
+type Failure = (&'static str, String);
+
+fn unsupported_parameter(name: &str) -> Failure {
+    (
+        error_codes::UNSUPPORTED_PARAMETER,
+        format!("[{}] is not a supported parameter.", name),
+    )
+}
+
+fn value_of(argument: &Parameter) -> &str {
+    argument.value().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ApplyError;
+    use crate::internal::{TargetSystem, VersionPolicy};
+    use crate::reference::Ref;
+    use crate::test_support::user;
+    use crate::User;
+
+    fn system(version: SoftwareVersion) -> SimulatedSystem<User> {
+        SimulatedSystem::new(version).with_object(user(45, "bob", 2))
+    }
+
+    #[test]
+    fn applied_changes_reach_the_table() {
+        let version = SoftwareVersion::new(4, 12);
+        let mut device = system(version);
+        let mut user = device.get(45).unwrap().clone();
+        *user.name = String::from("bob smith");
+        *user.role_id = Ref::new(3);
+
+        user.apply(&TargetSystem::new(version), &mut device)
+            .unwrap();
+
+        let stored = device.get(45).unwrap();
+        assert_eq!(*stored.name, "bob smith");
+        assert_eq!(*stored.role_id, Ref::new(3));
+        assert!(!user.is_changed());
+    }
+
+    #[test]
+    fn reset_flags_empty_fields() {
+        let mut device = system(SoftwareVersion::new(4, 12));
+        let command = ModificationCommand::new(
+            CommandType::Modify,
+            "update_user",
+            Some(String::from("45")),
+            vec![Parameter::Flag("-reset_name")],
+        );
+
+        assert!(device.run(&command).is_success());
+        assert_eq!(*device.get(45).unwrap().name, "");
+    }
+
+    #[test]
+    fn unsupported_fields_are_rejected_by_version() {
+        let version = SoftwareVersion::new(3, 188);
+        let mut device = system(version);
+        let mut user = device.get(45).unwrap().clone();
+        *user.role_id = Ref::new(3);
+        let target = TargetSystem::new(version).with_version_policy(VersionPolicy::Warn);
+
+        let error = user.apply(&target, &mut device).unwrap_err();
+
+        let ApplyError::Failed(output) = error else {
+            panic!("expected the device to reject the command");
+        };
+        assert_eq!(
+            output.error_code.as_deref(),
+            Some(error_codes::UNSUPPORTED_PARAMETER)
+        );
+        assert_eq!(*user.role_id, Ref::new(2));
+        assert_eq!(*device.get(45).unwrap().role_id, Ref::new(2));
+    }
+
+    #[test]
+    fn missing_objects_are_reported() {
+        let mut device = system(SoftwareVersion::new(4, 12));
+
+        let output = device.run(&ModificationCommand::delete(&user(7, "", 0)));
+
+        assert_eq!(
+            output.error_code.as_deref(),
+            Some(error_codes::NO_SUCH_OBJECT)
+        );
+        assert_eq!(device.objects().count(), 1);
+    }
+
+    #[test]
+    fn invalid_values_leave_the_object_untouched() {
+        let mut device = system(SoftwareVersion::new(4, 12));
+        let command = ModificationCommand::new(
+            CommandType::Modify,
+            "update_user",
+            Some(String::from("45")),
+            vec![
+                Parameter::Parameter("-set_name", String::from("alice")),
+                Parameter::Parameter("-set_roleid", String::from("admin")),
+            ],
+        );
+
+        let output = device.run(&command);
+
+        assert_eq!(
+            output.error_code.as_deref(),
+            Some(error_codes::INVALID_VALUE)
+        );
+        assert_eq!(*device.get(45).unwrap().name, "bob");
+    }
+
+    #[test]
+    fn create_assigns_ids_and_delete_removes_objects() {
+        let mut device = system(SoftwareVersion::new(4, 12));
+        let created = ModificationCommand::new(
+            CommandType::Create,
+            "make_user",
+            None,
+            vec![
+                Parameter::Parameter("-name", String::from("carol")),
+                Parameter::Parameter("-roleid", String::from("5")),
+            ],
+        );
+
+        let output = device.run(&created);
+
+        assert_eq!(output.stdout, "User, id [46], successfully created");
+        assert_eq!(*device.get(46).unwrap().name, "carol");
+        assert_eq!(*device.get(46).unwrap().role_id, Ref::new(5));
+
+        assert!(device
+            .run(&ModificationCommand::delete(&user(46, "", 0)))
+            .is_success());
+        assert!(device.get(46).is_none());
+    }
+
+    #[test]
+    fn create_fails_once_the_last_id_is_taken() {
+        let mut device = system(SoftwareVersion::new(4, 12)).with_object(user(u32::MAX, "max", 2));
+        let created = ModificationCommand::new(
+            CommandType::Create,
+            "make_user",
+            None,
+            vec![Parameter::Parameter("-name", String::from("carol"))],
+        );
+
+        let output = device.run(&created);
+
+        assert_eq!(output.error_code.as_deref(), Some(error_codes::NO_FREE_ID));
+        assert_eq!(device.objects().count(), 2);
+    }
+
+    #[test]
+    fn create_uses_the_requested_id() {
+        let version = SoftwareVersion::new(4, 12);
+        let mut device = system(version);
+        let created =
+            ModificationCommand::create(&user(60, "erin", 3), &TargetSystem::new(version)).unwrap();
+
+        assert_eq!(
+            device.run(&created).stdout,
+            "User, id [60], successfully created"
+        );
+        assert_eq!(*device.get(60).unwrap().name, "erin");
+
+        let again = device.run(&created);
+        assert_eq!(
+            again.error_code.as_deref(),
+            Some(error_codes::OBJECT_EXISTS)
+        );
+    }
+
+    #[test]
+    fn unknown_commands_and_switches_fail() {
+        let mut device = system(SoftwareVersion::new(4, 12));
+        let wrong_verb = ModificationCommand::new(CommandType::Modify, "chuser", None, vec![]);
+        let create_switch = ModificationCommand::new(
+            CommandType::Modify,
+            "update_user",
+            Some(String::from("45")),
+            vec![Parameter::Parameter("-name", String::from("alice"))],
+        );
+
+        assert_eq!(device.run(&wrong_verb).status, Some(127));
+        assert_eq!(
+            device.run(&create_switch).error_code.as_deref(),
+            Some(error_codes::UNSUPPORTED_PARAMETER)
+        );
+    }
+}
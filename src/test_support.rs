@@ -0,0 +1,34 @@
+//! Fixtures shared by the unit tests of every module.
+
+use crate::internal::{SoftwareVersion, SystemObject, TargetSystem};
+use crate::reference::Ref;
+use crate::{Role, User};
+
+pub(crate) fn target() -> TargetSystem {
+    TargetSystem::new(SoftwareVersion::new(4, 12))
+}
+
+pub(crate) fn user(id: u32, name: &str, role_id: u32) -> User {
+    let mut user = User::default();
+    *user.id = id;
+    *user.name = String::from(name);
+    *user.role_id = Ref::new(role_id);
+    user
+}
+
+pub(crate) fn role(id: u32, name: &str) -> Role {
+    let mut role = Role::default();
+    *role.id = id;
+    *role.name = String::from(name);
+    role
+}
+
+/// `object` with its current values as the baseline, as if read from the target system.
+pub(crate) fn stored<T: SystemObject>(mut object: T) -> T {
+    object.commit();
+    object
+}
+
+pub(crate) fn stored_user() -> User {
+    stored(user(45, "bob", 2))
+}
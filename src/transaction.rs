@@ -0,0 +1,311 @@
+//! Applying commands across several objects as one logical change. The target system
+//! has no transactions, so a failure is compensated by running the inverses of the
+//! commands that already succeeded.
+
+use std::{error::Error, fmt};
+
+use crate::executor::{run_checked, ApplyError, CommandExecutor, CommandOutput};
+use crate::internal::ModificationCommand;
+use crate::plan::{Plan, PlanEntry};
+use crate::undo::ReversibleCommand;
+
+/// Reversible commands that either all apply or are all backed out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    commands: Vec<ReversibleCommand>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Transaction::default()
+    }
+
+    pub fn with_command(mut self, command: ReversibleCommand) -> Self {
+        self.push(command);
+        self
+    }
+
+    pub fn push(&mut self, command: ReversibleCommand) {
+        self.commands.push(command);
+    }
+
+    pub fn commands(&self) -> &[ReversibleCommand] {
+        &self.commands
+    }
+
+    /// Runs the commands in order. If one fails, the inverses of the ones before it run
+    /// in reverse order; a failing inverse doesn't stop the others. A create counts as
+    /// failed if its object can't be backed out, see [`ReversibleCommand::applied`]; one
+    /// that the target ran under another id is backed out with the rest. Returns the
+    /// output of every command on success.
+    pub fn execute(
+        &self,
+        executor: &mut impl CommandExecutor,
+    ) -> Result<Vec<CommandOutput>, TransactionError> {
+        let mut outputs = Vec::with_capacity(self.commands.len());
+        let mut applied = Vec::with_capacity(self.commands.len());
+        for (index, command) in self.commands.iter().enumerate() {
+            let result = run_checked(executor, command.command())
+                .and_then(|output| Ok((command.applied(&output)?, output)));
+            match result {
+                Ok((command, output)) => {
+                    applied.push(command);
+                    outputs.push(output);
+                }
+                Err(error) => {
+                    if let ApplyError::UnexpectedId { reported, .. } = error {
+                        applied.push(command.clone().with_created_id(reported));
+                    }
+                    let rolled_back = applied
+                        .iter()
+                        .rev()
+                        .map(|command| RolledBack {
+                            command: command.inverse().clone(),
+                            result: run_checked(executor, command.inverse()),
+                        })
+                        .collect();
+                    return Err(TransactionError {
+                        failed: index,
+                        error,
+                        rolled_back,
+                    });
+                }
+            }
+        }
+        Ok(outputs)
+    }
+}
+
+impl From<&Plan> for Transaction {
+    fn from(plan: &Plan) -> Self {
+        Transaction {
+            commands: plan
+                .entries()
+                .iter()
+                .map(PlanEntry::reversible_command)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// An inverse command run while rolling a [`Transaction`] back.
+#[derive(Debug)]
+pub struct RolledBack {
+    pub command: ModificationCommand,
+    pub result: Result<CommandOutput, ApplyError>,
+}
+
+/// Why a [`Transaction`] was rolled back, and what the rollback did.
+#[derive(Debug)]
+pub struct TransactionError {
+    /// The index of the command that failed.
+    pub failed: usize,
+    pub error: ApplyError,
+    /// The inverses that were run, in the order they ran.
+    pub rolled_back: Vec<RolledBack>,
+}
+
+impl TransactionError {
+    /// Whether every inverse succeeded, so the target is back in its original state.
+    pub fn is_fully_rolled_back(&self) -> bool {
+        self.rolled_back.iter().all(|step| step.result.is_ok())
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed_inverses = self
+            .rolled_back
+            .iter()
+            .filter(|step| step.result.is_err())
+            .count();
+        write!(
+            f,
+            "command {} failed: {}; rolled back {} commands",
+            self.failed,
+            self.error,
+            self.rolled_back.len()
+        )?;
+        if failed_inverses > 0 {
+            write!(f, ", {} of which failed", failed_inverses)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for TransactionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::RecordingExecutor;
+    use crate::internal::{CommandType, SoftwareVersion};
+    use crate::reference::Ref;
+    use crate::render::QuoteStyle;
+    use crate::simulator::SimulatedSystem;
+    use crate::test_support::{stored, target, user};
+    use crate::User;
+
+    fn renamed(id: u32, from: &str, to: &str) -> ReversibleCommand {
+        let mut user = stored(user(id, from, 2));
+        *user.name = String::from(to);
+        ReversibleCommand::modify(&user, &target()).unwrap()
+    }
+
+    fn device() -> SimulatedSystem<User> {
+        SimulatedSystem::new(SoftwareVersion::new(4, 12))
+            .with_object(stored(user(45, "bob", 2)))
+            .with_object(stored(user(46, "carol", 2)))
+    }
+
+    fn names(device: &SimulatedSystem<User>) -> Vec<String> {
+        device
+            .objects()
+            .map(|user| user.name.get().clone())
+            .collect()
+    }
+
+    #[test]
+    fn successful_transactions_apply_every_command() {
+        let mut device = device();
+        let transaction = Transaction::new()
+            .with_command(renamed(45, "bob", "bob smith"))
+            .with_command(renamed(46, "carol", "carol jones"));
+
+        assert_eq!(transaction.execute(&mut device).unwrap().len(), 2);
+        assert_eq!(names(&device), vec!["bob smith", "carol jones"]);
+    }
+
+    #[test]
+    fn failures_roll_back_earlier_commands_in_reverse() {
+        let mut device = device();
+        let transaction = Transaction::new()
+            .with_command(renamed(45, "bob", "bob smith"))
+            .with_command(
+                ReversibleCommand::delete(&stored(user(46, "carol", 2)), &target()).unwrap(),
+            )
+            .with_command(renamed(47, "dave", "dave brown"));
+
+        let error = transaction.execute(&mut device).unwrap_err();
+
+        assert_eq!(error.failed, 2);
+        assert!(error.is_fully_rolled_back());
+        let rolled_back = error
+            .rolled_back
+            .iter()
+            .map(|step| step.command.command())
+            .collect::<Vec<_>>();
+        assert_eq!(rolled_back, vec!["make_user", "update_user"]);
+        assert_eq!(names(&device), vec!["bob", "carol"]);
+        assert_eq!(
+            error.to_string(),
+            "command 2 failed: command failed with CMMVC5753E; rolled back 2 commands"
+        );
+    }
+
+    #[test]
+    fn failing_inverses_are_reported_and_the_rest_still_run() {
+        let mut executor = RecordingExecutor::new()
+            .respond_with(CommandOutput::success(""))
+            .respond_with(CommandOutput::success(""))
+            .respond_with(CommandOutput::failure(1, "CMMVC5709E"))
+            .respond_with(CommandOutput::failure(1, "CMMVC5753E"));
+        let transaction = Transaction::new()
+            .with_command(renamed(45, "bob", "bob smith"))
+            .with_command(renamed(46, "carol", "carol jones"))
+            .with_command(renamed(47, "dave", "dave brown"));
+
+        let error = transaction.execute(&mut executor).unwrap_err();
+
+        assert!(!error.is_fully_rolled_back());
+        assert!(error.rolled_back[0].result.is_err());
+        assert!(error.rolled_back[1].result.is_ok());
+        assert_eq!(executor.commands().len(), 5);
+        assert!(error
+            .to_string()
+            .ends_with("rolled back 2 commands, 1 of which failed"));
+    }
+
+    #[test]
+    fn creates_roll_back_by_the_id_the_target_reports() {
+        let mut executor = RecordingExecutor::new()
+            .respond_with(CommandOutput::success(
+                "User, id [46], successfully created",
+            ))
+            .respond_with(CommandOutput::failure(1, "CMMVC5753E"));
+        let transaction = Transaction::new()
+            .with_command(ReversibleCommand::create(&user(0, "carol", 2), &target()).unwrap())
+            .with_command(renamed(47, "dave", "dave brown"));
+
+        let error = transaction.execute(&mut executor).unwrap_err();
+
+        assert_eq!(
+            executor.commands()[0].to_command_line(QuoteStyle::Posix),
+            "make_user -name carol -roleid 2"
+        );
+        assert_eq!(
+            error.rolled_back[0].command.command_type(),
+            CommandType::Delete
+        );
+        assert_eq!(error.rolled_back[0].command.object_id(), Some("46"));
+        assert_eq!(executor.commands()[2].object_id(), Some("46"));
+    }
+
+    #[test]
+    fn creates_under_another_id_fail_and_are_backed_out() {
+        let mut executor = RecordingExecutor::new()
+            .respond_with(CommandOutput::success(""))
+            .respond_with(CommandOutput::success(
+                "User, id [46], successfully created",
+            ));
+        let transaction = Transaction::new()
+            .with_command(renamed(45, "bob", "bob smith"))
+            .with_command(ReversibleCommand::create(&user(48, "erin", 2), &target()).unwrap());
+
+        let error = transaction.execute(&mut executor).unwrap_err();
+
+        assert_eq!(error.failed, 1);
+        assert_eq!(
+            error.to_string(),
+            "command 1 failed: object was created with id 46 instead of 48; rolled back 2 commands"
+        );
+        assert_eq!(error.rolled_back[0].command.object_id(), Some("46"));
+        assert_eq!(error.rolled_back[1].command.object_id(), Some("45"));
+    }
+
+    #[test]
+    fn creates_never_replace_existing_objects() {
+        let mut device = SimulatedSystem::new(SoftwareVersion::new(4, 12))
+            .with_object(stored(user(45, "bob", 2)));
+        let transaction = Transaction::new()
+            .with_command(ReversibleCommand::create(&user(45, "carol", 2), &target()).unwrap())
+            .with_command(
+                ReversibleCommand::delete(&stored(user(47, "dave", 2)), &target()).unwrap(),
+            );
+
+        let error = transaction.execute(&mut device).unwrap_err();
+
+        assert_eq!(error.failed, 0);
+        assert!(error.rolled_back.is_empty());
+        assert_eq!(names(&device), vec!["bob"]);
+    }
+
+    #[test]
+    fn plans_run_as_transactions() {
+        let mut device = device();
+        let desired = vec![user(45, "bob smith", 2), user(46, "carol", 9)];
+        let actual = device.objects().cloned().collect::<Vec<_>>();
+        let mut plan = Plan::new();
+        plan.reconcile(&desired, &actual, &target()).unwrap();
+
+        Transaction::from(&plan).execute(&mut device).unwrap();
+
+        assert_eq!(names(&device), vec!["bob smith", "carol"]);
+        assert_eq!(*device.get(46).unwrap().role_id, Ref::new(9));
+    }
+}
@@ -0,0 +1,355 @@
+//! Inverse commands, so that every change can be backed out. Backing out a delete brings
+//! the object back under its old id, so the object type's create command has to set it,
+//! see [`SystemObject::id_switch`]. Backing out a create needs the new object's id, which
+//! is the requested one if the create sets it, and otherwise the one the target reports.
+
+use crate::executor::{ApplyError, CommandOutput};
+use crate::internal::{
+    short_type_name, CommandError, CommandType, CreatableObject, DeletableObject, ModifiableObject,
+    ModificationCommand, Object, SystemObject, TargetSystem,
+};
+use crate::render::QuoteStyle;
+
+/// A command together with the command that reverses it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReversibleCommand {
+    command: ModificationCommand,
+    inverse: ModificationCommand,
+}
+
+impl ReversibleCommand {
+    pub fn new(command: ModificationCommand, inverse: ModificationCommand) -> Self {
+        ReversibleCommand { command, inverse }
+    }
+
+    /// The modify command for the changed fields of `object`, and the one that sets them
+    /// back to their baselines. A field that was empty before is reset with its reset
+    /// flag.
+    pub fn modify<ObjectType>(
+        object: &ObjectType,
+        target: &TargetSystem,
+    ) -> Result<Self, CommandError>
+    where
+        ObjectType: SystemObject + ModifiableObject + Clone,
+    {
+        let Object { old, new } = Object::from_tracked(object);
+        Ok(ReversibleCommand {
+            command: ModificationCommand::modify(object, target)?,
+            inverse: Object::new(new, old).modify_command(target)?,
+        })
+    }
+
+    /// The create command for `object`, and the delete command for it. If the create
+    /// leaves the id to the target system, because the type's create command can't set it
+    /// or `object` has the default id, the delete has no object id until
+    /// [`with_created_id`](Self::with_created_id) gives it the one the target reports.
+    pub fn create<ObjectType>(
+        object: &ObjectType,
+        target: &TargetSystem,
+    ) -> Result<Self, CommandError>
+    where
+        ObjectType: SystemObject + CreatableObject + DeletableObject,
+    {
+        let command = ModificationCommand::create(object, target)?;
+        let object_id = command.sets_id(object).then(|| object.get_id().to_string());
+        Ok(ReversibleCommand {
+            command,
+            inverse: ModificationCommand::new(
+                CommandType::Delete,
+                object.get_delete_command(),
+                object_id,
+                Vec::new(),
+            ),
+        })
+    }
+
+    /// The delete command for `snapshot`, and the create command that brings it back as
+    /// it was, under the same id so that references to it still hold. Objects whose
+    /// create command doesn't set the id would come back under a new one, so they are
+    /// rejected with [`CommandError::IdNotSettable`].
+    pub fn delete<ObjectType>(
+        snapshot: &ObjectType,
+        target: &TargetSystem,
+    ) -> Result<Self, CommandError>
+    where
+        ObjectType: SystemObject + CreatableObject + DeletableObject,
+    {
+        let inverse = ModificationCommand::create(snapshot, target)?;
+        if !inverse.sets_id(snapshot) {
+            return Err(CommandError::IdNotSettable {
+                object_type: short_type_name::<ObjectType>(),
+                object_id: snapshot.get_id(),
+            });
+        }
+        Ok(ReversibleCommand {
+            command: ModificationCommand::delete(snapshot),
+            inverse,
+        })
+    }
+
+    pub fn command(&self) -> &ModificationCommand {
+        &self.command
+    }
+
+    pub fn inverse(&self) -> &ModificationCommand {
+        &self.inverse
+    }
+
+    /// Points the inverse of a create at `id`, the id the target system reports for the
+    /// created object. Other commands are returned unchanged.
+    pub fn with_created_id(self, id: u32) -> Self {
+        if self.command.command_type() != CommandType::Create {
+            return self;
+        }
+        let inverse = ModificationCommand::new(
+            self.inverse.command_type(),
+            self.inverse.command(),
+            Some(id.to_string()),
+            self.inverse.arguments().to_vec(),
+        );
+        ReversibleCommand {
+            command: self.command,
+            inverse,
+        }
+    }
+
+    /// This command as applied by a target that answered with `output`. The inverse of a
+    /// create that left the id to the target is pointed at the id the target reports.
+    /// A create whose object can't be backed out fails: with
+    /// [`ApplyError::UnexpectedId`] if the target used another id than the requested one,
+    /// and with [`ApplyError::MissingId`] if it was left to choose and reports none.
+    pub fn applied(&self, output: &CommandOutput) -> Result<Self, ApplyError> {
+        if self.command.command_type() != CommandType::Create {
+            return Ok(self.clone());
+        }
+        match (self.inverse.object_id(), output.created_id()) {
+            (Some(requested), Some(reported)) if requested != reported.to_string() => {
+                Err(ApplyError::UnexpectedId {
+                    requested: String::from(requested),
+                    reported,
+                })
+            }
+            (Some(_), _) => Ok(self.clone()),
+            (None, Some(reported)) => Ok(self.clone().with_created_id(reported)),
+            (None, None) => Err(ApplyError::MissingId(output.clone())),
+        }
+    }
+
+    /// Swaps the command and its inverse.
+    pub fn reversed(self) -> Self {
+        ReversibleCommand {
+            command: self.inverse,
+            inverse: self.command,
+        }
+    }
+}
+
+/// Writes the inverses of `commands` as a script, one command per line, in the reverse of
+/// the order the commands run in.
+pub fn back_out_script<'a>(
+    commands: impl DoubleEndedIterator<Item = &'a ReversibleCommand>,
+    style: QuoteStyle,
+) -> String {
+    commands
+        .rev()
+        .map(|command| command.inverse().to_command_line(style) + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{Parameter, SoftwareVersion};
+    use crate::reference::Ref;
+    use crate::simulator::SimulatedSystem;
+    use crate::system_object;
+    use crate::test_support::{stored, target, user};
+
+    #[test]
+    fn modify_undoes_to_the_baseline() {
+        let mut user = stored(user(45, "bob", 2));
+        *user.name = String::from("alice");
+        *user.role_id = Ref::new(3);
+
+        let command = ReversibleCommand::modify(&user, &target()).unwrap();
+
+        assert_eq!(
+            command.inverse().arguments(),
+            &[
+                Parameter::Parameter("-set_name", String::from("bob")),
+                Parameter::Parameter("-set_roleid", String::from("2")),
+            ]
+        );
+        assert_eq!(command.inverse().object_id(), Some("45"));
+    }
+
+    #[test]
+    fn setting_an_empty_field_undoes_to_its_reset_flag() {
+        let mut user = stored(user(45, "", 2));
+        *user.name = String::from("bob");
+
+        let command = ReversibleCommand::modify(&user, &target()).unwrap();
+
+        assert_eq!(
+            command.command().arguments(),
+            &[Parameter::Parameter("-set_name", String::from("bob"))]
+        );
+        assert_eq!(
+            command.inverse().arguments(),
+            &[Parameter::Flag("-reset_name")]
+        );
+    }
+
+    #[test]
+    fn create_and_delete_undo_each_other() {
+        let user = stored(user(45, "bob", 2));
+
+        let create = ReversibleCommand::create(&user, &target()).unwrap();
+        let delete = ReversibleCommand::delete(&user, &target()).unwrap();
+
+        assert_eq!(create.inverse(), &ModificationCommand::delete(&user));
+        assert_eq!(delete.inverse(), create.command());
+        assert_eq!(delete.reversed(), create);
+    }
+
+    #[test]
+    fn inverses_restore_the_target_system() {
+        let mut device = SimulatedSystem::new(SoftwareVersion::new(4, 12))
+            .with_object(stored(user(45, "", 2)))
+            .with_object({
+                let mut other = stored(user(45, "carol", 2));
+                *other.id = 46;
+                other
+            });
+        let mut user = device.get(45).unwrap().clone();
+        *user.name = String::from("bob");
+        let commands = [
+            ReversibleCommand::modify(&user, &target()).unwrap(),
+            ReversibleCommand::delete(device.get(46).unwrap(), &target()).unwrap(),
+        ];
+
+        for command in &commands {
+            assert!(device.run(command.command()).is_success());
+        }
+        for command in commands.iter().rev() {
+            assert!(device.run(command.inverse()).is_success());
+        }
+
+        assert_eq!(*device.get(45).unwrap().name, "");
+        assert_eq!(
+            device
+                .objects()
+                .map(|user| user.name.get().clone())
+                .collect::<Vec<_>>(),
+            vec!["", "carol"]
+        );
+    }
+
+    #[test]
+    fn deleted_objects_come_back_under_their_old_id() {
+        let mut device = SimulatedSystem::new(SoftwareVersion::new(4, 12))
+            .with_object(stored(user(45, "bob", 2)))
+            .with_object({
+                let mut other = stored(user(45, "carol", 2));
+                *other.id = 46;
+                other
+            });
+        let delete = ReversibleCommand::delete(device.get(45).unwrap(), &target()).unwrap();
+
+        assert!(device.run(delete.command()).is_success());
+        assert!(device.run(delete.inverse()).is_success());
+
+        let restored = device.get(45).unwrap();
+        assert_eq!(*restored.name, "bob");
+        assert_eq!(*restored.role_id, Ref::new(2));
+        assert_eq!(device.objects().count(), 2);
+    }
+
+    #[system_object(create = "make_tag", delete = "remove_tag")]
+    struct Tag {
+        #[field(id)]
+        id: u32,
+        #[field(param = "-name")]
+        name: String,
+    }
+
+    #[test]
+    fn objects_without_a_create_id_switch_cant_be_restored() {
+        let mut tag = Tag::default();
+        *tag.id = 7;
+
+        assert_eq!(
+            ReversibleCommand::delete(&tag, &target()),
+            Err(CommandError::IdNotSettable {
+                object_type: "Tag",
+                object_id: 7
+            })
+        );
+        assert_eq!(
+            ReversibleCommand::delete(&stored(user(0, "carol", 2)), &target()),
+            Err(CommandError::IdNotSettable {
+                object_type: "User",
+                object_id: 0
+            })
+        );
+    }
+
+    #[test]
+    fn creates_that_leave_the_id_to_the_target_undo_by_the_reported_id() {
+        let mut tag = Tag::default();
+        *tag.id = 7;
+
+        for create in [
+            ReversibleCommand::create(&tag, &target()).unwrap(),
+            ReversibleCommand::create(&user(0, "carol", 2), &target()).unwrap(),
+        ] {
+            assert_eq!(create.inverse().object_id(), None);
+            assert_eq!(create.with_created_id(46).inverse().object_id(), Some("46"));
+        }
+    }
+
+    #[test]
+    fn applied_creates_check_the_id_the_target_reports() {
+        let requested = ReversibleCommand::create(&user(48, "erin", 3), &target()).unwrap();
+        let assigned = ReversibleCommand::create(&user(0, "erin", 3), &target()).unwrap();
+        let created =
+            |id| CommandOutput::success(format!("User, id [{}], successfully created", id));
+
+        assert_eq!(requested.applied(&created(48)).unwrap(), requested);
+        assert_eq!(
+            requested.applied(&CommandOutput::success("")).unwrap(),
+            requested
+        );
+        assert!(matches!(
+            requested.applied(&created(46)),
+            Err(ApplyError::UnexpectedId { reported: 46, .. })
+        ));
+        assert_eq!(
+            assigned
+                .applied(&created(46))
+                .unwrap()
+                .inverse()
+                .object_id(),
+            Some("46")
+        );
+        assert!(matches!(
+            assigned.applied(&CommandOutput::success("")),
+            Err(ApplyError::MissingId(_))
+        ));
+    }
+
+    #[test]
+    fn back_out_scripts_run_in_reverse() {
+        let mut user = stored(user(45, "bob", 2));
+        *user.role_id = Ref::new(3);
+        let commands = [
+            ReversibleCommand::modify(&user, &target()).unwrap(),
+            ReversibleCommand::delete(&user, &target()).unwrap(),
+        ];
+
+        assert_eq!(
+            back_out_script(commands.iter(), QuoteStyle::Posix),
+            "make_user -id 45 -name bob -roleid 3\nupdate_user -set_roleid 2 45\n"
+        );
+    }
+}